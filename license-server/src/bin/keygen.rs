@@ -1,9 +1,60 @@
 use base64::{engine::general_purpose, Engine as _};
 use ed25519_dalek::SigningKey;
 use std::fs;
-use std::io::Write;
+
+#[cfg(feature = "encrypted-key")]
+#[path = "../keycrypt.rs"]
+mod keycrypt;
+
+/// The passphrase for `--encrypt`: `KEY_PASSPHRASE` if set, otherwise typed
+/// in twice at the terminal without echo
+#[cfg(feature = "encrypted-key")]
+fn read_passphrase() -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var("KEY_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let prompt = |label: &str| {
+        rpassword::prompt_password(format!("{}: ", label))
+            .map_err(|e| format!("Failed to read passphrase: {}", e))
+    };
+    let passphrase = prompt("Passphrase for the private key")?;
+    if prompt("Repeat the passphrase")? != passphrase {
+        return Err("Passphrases don't match".to_string());
+    }
+    Ok(passphrase)
+}
+
+/// The `.env` line holding the private key: as-is, or with `--encrypt`
+/// sealed under a passphrase as `PRIVATE_KEY_ENCRYPTED`
+#[cfg(feature = "encrypted-key")]
+fn private_key_line(private_key_b64: &str, encrypt: bool) -> Result<String, String> {
+    if !encrypt {
+        return Ok(format!("PRIVATE_KEY={}", private_key_b64));
+    }
+    let passphrase = read_passphrase()?;
+    let encrypted = keycrypt::encrypt_private_key(private_key_b64, &passphrase)?;
+    // The .env copy is the only one, so make sure it opens before writing it
+    if keycrypt::decrypt_private_key(&encrypted, &passphrase)? != private_key_b64 {
+        return Err("Encrypted key failed to decrypt again".to_string());
+    }
+    Ok(format!("PRIVATE_KEY_ENCRYPTED={}", encrypted))
+}
+
+#[cfg(not(feature = "encrypted-key"))]
+fn private_key_line(private_key_b64: &str, encrypt: bool) -> Result<String, String> {
+    if encrypt {
+        return Err("--encrypt needs keygen built with --features encrypted-key".to_string());
+    }
+    Ok(format!("PRIVATE_KEY={}", private_key_b64))
+}
+
+/// The shell line that bakes the public key into an app build
+fn public_key_export_line(public_key_b64: &str) -> String {
+    format!("export LOCALENDAR_PUBLIC_KEY={}", public_key_b64)
+}
 
 fn main() {
+    let encrypt = std::env::args().skip(1).any(|arg| arg == "--encrypt");
     println!("🔑 Generating Ed25519 keypair...\n");
 
     // Generate signing key
@@ -16,10 +67,21 @@ fn main() {
     let public_key_b64 = general_purpose::STANDARD.encode(verifying_key.to_bytes());
 
     println!("✅ Keypair generated!\n");
-    println!("PUBLIC KEY (add to src-tauri/src/licensing.rs):");
-    println!("{}\n", public_key_b64);
-    println!("PRIVATE KEY (add to .env - KEEP SECRET!):");
-    println!("{}\n", private_key_b64);
+    println!("PUBLIC KEY (set when building the app):");
+    println!("{}\n", public_key_export_line(&public_key_b64));
+    let private_key_line = match private_key_line(&private_key_b64, encrypt) {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if encrypt {
+        println!("PRIVATE KEY: encrypted into .env; start the server with KEY_PASSPHRASE set\n");
+    } else {
+        println!("PRIVATE KEY (add to .env - KEEP SECRET!):");
+        println!("{}\n", private_key_b64);
+    }
 
     // Create .env file
     let env_content = format!(
@@ -27,7 +89,7 @@ fn main() {
 # KEEP THIS FILE SECRET! DO NOT COMMIT TO VERSION CONTROL!
 
 # Ed25519 Private Key for signing licenses
-PRIVATE_KEY={}
+{}
 
 # Server Configuration
 PORT=3001
@@ -35,7 +97,7 @@ PORT=3001
 # Product ID
 PRODUCT_ID=localendar-mvp
 "#,
-        private_key_b64
+        private_key_line
     );
 
     if let Err(e) = fs::write(".env", env_content) {
@@ -62,9 +124,10 @@ PRODUCT_ID=localendar-mvp
     // Create public key file
     let public_key_content = format!(
         r#"# LoCalendar Public Key
-# Add this to src-tauri/src/licensing.rs
+# Export this in the environment that builds the app (src-tauri);
+# release builds fail to compile without it.
 
-const PUBLIC_KEY_BASE64: &str = "{}";
+export LOCALENDAR_PUBLIC_KEY={}
 "#,
         public_key_b64
     );
@@ -84,8 +147,41 @@ const PUBLIC_KEY_BASE64: &str = "{}";
     }
 
     println!("\n📋 Next steps:");
-    println!("1. Copy PUBLIC KEY to src-tauri/src/licensing.rs");
+    println!(
+        "1. Build the app with: {}",
+        public_key_export_line(&public_key_b64)
+    );
     println!("2. PRIVATE KEY is in .env (never share or commit!)");
     println!("3. Run: cargo run");
     println!("4. Server will start on port 3001");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_line_sets_the_apps_build_variable() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key_b64 =
+            general_purpose::STANDARD.encode(signing_key.verifying_key().to_bytes());
+        let line = public_key_export_line(&public_key_b64);
+        assert_eq!(
+            line,
+            format!("export LOCALENDAR_PUBLIC_KEY={}", public_key_b64)
+        );
+
+        // What the shell would set decodes back to the key
+        let value = line.strip_prefix("export LOCALENDAR_PUBLIC_KEY=").unwrap();
+        let bytes = general_purpose::STANDARD.decode(value).unwrap();
+        assert_eq!(bytes, signing_key.verifying_key().to_bytes());
+        assert!(!value.contains(char::is_whitespace));
+    }
+
+    #[test]
+    fn private_key_line_is_plain_without_encrypt() {
+        assert_eq!(private_key_line("abc=", false).unwrap(), "PRIVATE_KEY=abc=");
+        #[cfg(not(feature = "encrypted-key"))]
+        assert!(private_key_line("abc=", true).is_err());
+    }
+}
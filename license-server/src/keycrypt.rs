@@ -0,0 +1,114 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+/// Version segment of an encrypted key, so the format can change later
+const VERSION: &str = "v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Argon2id over the passphrase: the key that seals the private key
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive key from passphrase: {}", e))?;
+    Ok(Key::from(key))
+}
+
+/// Seal the base64 private key with `passphrase` (Argon2id, then
+/// XChaCha20-Poly1305), for `PRIVATE_KEY_ENCRYPTED`. The result is
+/// `v1.<salt>.<nonce>.<ciphertext>`, each part base64.
+pub fn encrypt_private_key(private_key_b64: &str, passphrase: &str) -> Result<String, String> {
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty".to_string());
+    }
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?)
+        .encrypt(&XNonce::from(nonce), private_key_b64.as_bytes())
+        .map_err(|_| "Failed to encrypt private key".to_string())?;
+    Ok([
+        VERSION.to_string(),
+        general_purpose::STANDARD.encode(salt),
+        general_purpose::STANDARD.encode(nonce),
+        general_purpose::STANDARD.encode(ciphertext),
+    ]
+    .join("."))
+}
+
+/// The base64 private key sealed by `encrypt_private_key`. A wrong
+/// passphrase fails the authentication tag rather than yielding garbage.
+pub fn decrypt_private_key(encrypted: &str, passphrase: &str) -> Result<String, String> {
+    let parts: Vec<&str> = encrypted.trim().split('.').collect();
+    let [VERSION, salt, nonce, ciphertext] = parts[..] else {
+        return Err(format!(
+            "Encrypted key must look like {}.<salt>.<nonce>.<ciphertext>",
+            VERSION
+        ));
+    };
+    let decode = |part: &str| {
+        general_purpose::STANDARD
+            .decode(part)
+            .map_err(|_| "Encrypted key is not valid base64".to_string())
+    };
+    let salt = decode(salt)?;
+    let nonce: [u8; NONCE_LEN] = decode(nonce)?
+        .try_into()
+        .map_err(|_| format!("Encrypted key nonce must be {} bytes", NONCE_LEN))?;
+
+    let plaintext = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?)
+        .decrypt(&XNonce::from(nonce), decode(ciphertext)?.as_slice())
+        .map_err(|_| "Wrong KEY_PASSPHRASE, or the encrypted key is corrupt".to_string())?;
+    String::from_utf8(plaintext).map_err(|_| "Decrypted key is not text".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+
+    #[test]
+    fn encrypted_keys_round_trip() {
+        let encrypted = encrypt_private_key(PRIVATE_KEY, "correct horse").unwrap();
+        assert!(encrypted.starts_with("v1."), "{}", encrypted);
+        assert!(!encrypted.contains(PRIVATE_KEY));
+        assert_eq!(
+            decrypt_private_key(&encrypted, "correct horse").unwrap(),
+            PRIVATE_KEY
+        );
+        assert_eq!(
+            decrypt_private_key(&format!(" {}\n", encrypted), "correct horse").unwrap(),
+            PRIVATE_KEY
+        );
+
+        // A fresh salt and nonce every time
+        assert_ne!(
+            encrypt_private_key(PRIVATE_KEY, "correct horse").unwrap(),
+            encrypted
+        );
+        assert!(encrypt_private_key(PRIVATE_KEY, "").is_err());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let encrypted = encrypt_private_key(PRIVATE_KEY, "correct horse").unwrap();
+        let error = decrypt_private_key(&encrypted, "battery staple").unwrap_err();
+        assert_eq!(
+            error,
+            "Wrong KEY_PASSPHRASE, or the encrypted key is corrupt"
+        );
+
+        // Tampering fails the same way
+        let mut parts: Vec<String> = encrypted.split('.').map(String::from).collect();
+        let mut ciphertext = general_purpose::STANDARD.decode(&parts[3]).unwrap();
+        ciphertext[0] ^= 1;
+        parts[3] = general_purpose::STANDARD.encode(ciphertext);
+        assert!(decrypt_private_key(&parts.join("."), "correct horse").is_err());
+
+        assert!(decrypt_private_key(PRIVATE_KEY, "correct horse").is_err());
+        assert!(decrypt_private_key(&encrypted.replacen("v1", "v2", 1), "correct horse").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+const DEFAULT_EXPIRES_DAYS: i64 = 365;
+
+/// Issuance rules for one plan. Fields left out of the policy file fall back
+/// to the built-in defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlanPolicy {
+    /// Days a lapsed license keeps working; stamped into the token
+    pub grace_days: Option<i64>,
+    /// Marks the plan as a fixed-length trial
+    pub trial_days: Option<i64>,
+    pub default_expires_days: i64,
+    pub max_expires_days: Option<i64>,
+    /// Once expired, clients fall back to the free plan instead of locking
+    /// the user out; stamped into the token
+    pub downgrade_on_expiry: bool,
+    /// Reject requests longer than the plan allows instead of clamping them
+    pub reject_over_cap: bool,
+}
+
+impl Default for PlanPolicy {
+    fn default() -> Self {
+        Self {
+            grace_days: None,
+            trial_days: None,
+            default_expires_days: DEFAULT_EXPIRES_DAYS,
+            max_expires_days: None,
+            downgrade_on_expiry: false,
+            reject_over_cap: false,
+        }
+    }
+}
+
+impl PlanPolicy {
+    /// Resolve the expiry (in days, `<= 0` meaning perpetual) for a request.
+    /// Trials always run for their configured length; otherwise the request
+    /// wins, capped at `max_expires_days` (which also rules out perpetual).
+    /// With `reject_over_cap`, a request beyond the cap is an error instead.
+    pub fn expires_days(&self, requested: Option<i64>) -> Result<i64, String> {
+        let over = |days: i64, cap: i64| days <= 0 || days > cap;
+        if let Some(trial_days) = self.trial_days {
+            if self.reject_over_cap && requested.is_some_and(|days| over(days, trial_days)) {
+                return Err(format!("Plan allows at most {} days", trial_days));
+            }
+            return Ok(trial_days);
+        }
+        let days = requested.unwrap_or(self.default_expires_days);
+        match self.max_expires_days {
+            Some(max) if over(days, max) && self.reject_over_cap => {
+                Err(format!("Plan allows at most {} days", max))
+            }
+            Some(max) if over(days, max) => Ok(max),
+            _ => Ok(days),
+        }
+    }
+}
+
+/// Plan policies loaded from `POLICIES_PATH`, e.g.
+/// `{ "trial": { "trial_days": 14 }, "pro": { "grace_days": 14 } }`
+#[derive(Debug, Clone, Default)]
+pub struct Policies {
+    plans: HashMap<String, PlanPolicy>,
+}
+
+impl Policies {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read policies {}: {}", path.display(), e))?;
+        let plans = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse policies {}: {}", path.display(), e))?;
+        Ok(Self { plans })
+    }
+
+    pub fn for_plan(&self, plan: &str) -> PlanPolicy {
+        self.plans.get(plan).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `json` to a fresh policy file and load it
+    fn load(json: &str) -> Result<Policies, String> {
+        let path =
+            std::env::temp_dir().join(format!("policies-test-{}.json", rand::random::<u64>()));
+        std::fs::write(&path, json).unwrap();
+        let policies = Policies::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        policies
+    }
+
+    #[test]
+    fn trial_runs_for_its_configured_length() {
+        let policies =
+            load(r#"{ "trial": { "trial_days": 14 }, "pro": { "grace_days": 7 } }"#).unwrap();
+
+        let trial = policies.for_plan("trial");
+        assert_eq!(trial.trial_days, Some(14));
+        assert_eq!(trial.expires_days(None), Ok(14));
+        // Whatever the request asks for
+        assert_eq!(trial.expires_days(Some(365)), Ok(14));
+        assert_eq!(trial.expires_days(Some(0)), Ok(14));
+    }
+
+    #[test]
+    fn missing_entries_fall_back_to_the_defaults() {
+        let policies = load(r#"{ "pro": { "grace_days": 7 } }"#).unwrap();
+
+        let pro = policies.for_plan("pro");
+        assert_eq!(pro.grace_days, Some(7));
+        assert_eq!(pro.default_expires_days, DEFAULT_EXPIRES_DAYS);
+        assert_eq!(pro.expires_days(None), Ok(DEFAULT_EXPIRES_DAYS));
+
+        let unlisted = policies.for_plan("enterprise");
+        assert_eq!(unlisted.grace_days, None);
+        assert_eq!(unlisted.expires_days(Some(30)), Ok(30));
+    }
+
+    #[test]
+    fn unreadable_policies_are_an_error() {
+        assert!(load("{ not json").is_err());
+        assert!(Policies::load("/nonexistent/policies.json").is_err());
+    }
+
+    #[test]
+    fn requests_over_the_cap_are_clamped() {
+        let policies =
+            load(r#"{ "trial": { "trial_days": 14 }, "pro": { "max_expires_days": 365 } }"#)
+                .unwrap();
+
+        let trial = policies.for_plan("trial");
+        assert_eq!(trial.expires_days(Some(5 * 365)), Ok(14));
+
+        let pro = policies.for_plan("pro");
+        assert_eq!(pro.expires_days(Some(90)), Ok(90));
+        assert_eq!(pro.expires_days(Some(365)), Ok(365));
+        assert_eq!(pro.expires_days(Some(5 * 365)), Ok(365));
+        // A capped plan can't be perpetual either
+        assert_eq!(pro.expires_days(Some(0)), Ok(365));
+    }
+
+    #[test]
+    fn requests_over_the_cap_can_be_rejected() {
+        let policies = load(
+            r#"{
+                "trial": { "trial_days": 14, "reject_over_cap": true },
+                "pro": { "max_expires_days": 365, "reject_over_cap": true }
+            }"#,
+        )
+        .unwrap();
+
+        let trial = policies.for_plan("trial");
+        assert_eq!(trial.expires_days(None), Ok(14));
+        assert_eq!(trial.expires_days(Some(7)), Ok(14));
+        assert_eq!(
+            trial.expires_days(Some(15)),
+            Err("Plan allows at most 14 days".to_string())
+        );
+
+        let pro = policies.for_plan("pro");
+        assert_eq!(pro.expires_days(Some(365)), Ok(365));
+        assert_eq!(
+            pro.expires_days(Some(366)),
+            Err("Plan allows at most 365 days".to_string())
+        );
+        assert_eq!(
+            pro.expires_days(Some(0)),
+            Err("Plan allows at most 365 days".to_string())
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Past this many tracked clients, finished windows are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// An IP address or CIDR block from `TRUSTED_IPS`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Parse `10.0.0.0/8`, `2001:db8::/32`, or a bare address (a /32 or /128)
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let (addr, prefix) = match input.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (input, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid IP address '{}'", input))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in '{}'", input))?,
+            None => max_prefix,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Compare IPv4-mapped IPv6 peers (from dual-stack sockets) as IPv4
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse `TRUSTED_IPS` (comma-separated addresses or CIDR blocks)
+pub fn parse_trusted_ips(list: &str) -> Result<Vec<IpNet>, String> {
+    list.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(IpNet::parse)
+        .collect()
+}
+
+/// Fixed-window request limit per client IP. Trusted IPs (our own billing
+/// backend and other automation) are never limited.
+pub struct RateLimiter {
+    /// Requests allowed per IP per minute; 0 disables limiting
+    per_minute: u32,
+    trusted: Vec<IpNet>,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, trusted: Vec<IpNet>) -> Self {
+        Self {
+            per_minute,
+            trusted,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|net| net.contains(ip))
+    }
+
+    /// Count a request from `ip`; `false` once it's over the limit
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        if self.per_minute == 0 || self.is_trusted(ip) {
+            return true;
+        }
+        let mut windows = match self.windows.lock() {
+            Ok(windows) => windows,
+            // Rather serve unlimited than fail every request
+            Err(_) => return true,
+        };
+        let now = Instant::now();
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < WINDOW);
+        }
+
+        let (started, count) = windows.entry(ip).or_insert((now, 0));
+        if now.duration_since(*started) >= WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.per_minute
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn trusted_ips_bypass_the_limit() {
+        let trusted = parse_trusted_ips("10.0.0.0/8, 203.0.113.7,2001:db8::/32").unwrap();
+        let limiter = RateLimiter::new(2, trusted);
+
+        for client in ["10.1.2.3", "203.0.113.7", "2001:db8::1", "::ffff:10.9.9.9"] {
+            assert!((0..10).all(|_| limiter.allow(ip(client))), "{}", client);
+        }
+
+        // Everyone else is throttled, each on their own count
+        for client in ["203.0.113.8", "11.0.0.1", "2001:db9::1"] {
+            assert!(limiter.allow(ip(client)));
+            assert!(limiter.allow(ip(client)));
+            assert!(!limiter.allow(ip(client)), "{}", client);
+        }
+    }
+
+    #[test]
+    fn trusted_ip_lists_are_validated() {
+        assert_eq!(parse_trusted_ips("").unwrap(), Vec::new());
+        assert_eq!(parse_trusted_ips(" , ").unwrap(), Vec::new());
+        assert!(parse_trusted_ips("10.0.0.0/33").is_err());
+        assert!(parse_trusted_ips("2001:db8::/129").is_err());
+        assert!(parse_trusted_ips("10.0.0.0/8,example.com").is_err());
+
+        let all = IpNet::parse("0.0.0.0/0").unwrap();
+        assert!(all.contains(ip("198.51.100.1")));
+        assert!(!all.contains(ip("2001:db8::1")));
+    }
+}
@@ -0,0 +1,140 @@
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier, VerifyingKey};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Holder of the license signing key. Everything the server signs (licenses,
+/// server times, offline activations) goes through this, so the private key
+/// can live in an HSM instead of the process. `sign` may block (the PKCS#11
+/// signer waits on a subprocess), so async code calls it through
+/// `sign_blocking` rather than directly.
+pub trait Signer: Send + Sync {
+    fn sign(&self, message: &[u8]) -> Result<Signature, String>;
+    fn verifying_key(&self) -> VerifyingKey;
+
+    /// Whether the private key lives in this process. Only such a key may be
+    /// swapped by `/rotate-key`, which always installs an in-memory key.
+    fn in_memory(&self) -> bool {
+        false
+    }
+}
+
+/// The key loaded from `PRIVATE_KEY`, held in memory
+pub struct InMemorySigner(SigningKey);
+
+impl InMemorySigner {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self(signing_key)
+    }
+}
+
+impl Signer for InMemorySigner {
+    fn sign(&self, message: &[u8]) -> Result<Signature, String> {
+        Ok(self.0.sign(message))
+    }
+
+    fn verifying_key(&self) -> VerifyingKey {
+        self.0.verifying_key()
+    }
+
+    fn in_memory(&self) -> bool {
+        true
+    }
+}
+
+/// An Ed25519 key inside a PKCS#11 token (an HSM, YubiHSM, smart card...).
+/// Signing runs OpenSC's `pkcs11-tool` against the vendor's module; the
+/// private key never leaves the device.
+pub struct Pkcs11Signer {
+    /// Path to the vendor's PKCS#11 module, e.g. `/usr/lib/yubihsm_pkcs11.so`
+    module: String,
+    /// Object id of the key on the token, in hex
+    key_id: String,
+    pin: String,
+    /// The public half, which the token doesn't hand out through this path
+    verifying_key: VerifyingKey,
+}
+
+impl Pkcs11Signer {
+    pub fn new(module: String, key_id: String, pin: String, verifying_key: VerifyingKey) -> Self {
+        Self {
+            module,
+            key_id,
+            pin,
+            verifying_key,
+        }
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn sign(&self, message: &[u8]) -> Result<Signature, String> {
+        let mut child = Command::new("pkcs11-tool")
+            // OpenSC reads `env:NAME` PINs from the environment, which unlike
+            // argv isn't visible to other users through `ps`
+            .args([
+                "--module",
+                &self.module,
+                "--login",
+                "--pin",
+                "env:PKCS11_PIN",
+            ])
+            .env("PKCS11_PIN", &self.pin)
+            .args(["--sign", "--mechanism", "EDDSA", "--id", &self.key_id])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run pkcs11-tool: {}", e))?;
+        child
+            .stdin
+            .take()
+            .ok_or("pkcs11-tool has no stdin")?
+            .write_all(message)
+            .map_err(|e| format!("Failed to send message to pkcs11-tool: {}", e))?;
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to run pkcs11-tool: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "HSM signing failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let signature = Signature::from_slice(&output.stdout)
+            .map_err(|_| format!("HSM returned a {}-byte signature", output.stdout.len()))?;
+        // Catch a wrong key id or PUBLIC_KEY before a bad signature goes out
+        self.verifying_key
+            .verify(message, &signature)
+            .map_err(|_| "HSM signature does not verify against PUBLIC_KEY".to_string())?;
+        Ok(signature)
+    }
+
+    fn verifying_key(&self) -> VerifyingKey {
+        self.verifying_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_signatures_verify_against_its_key() {
+        let signer = InMemorySigner::new(SigningKey::from_bytes(&[7; 32]));
+        let signature = signer.sign(b"message").unwrap();
+        assert!(signer
+            .verifying_key()
+            .verify(b"message", &signature)
+            .is_ok());
+        assert!(signer
+            .verifying_key()
+            .verify(b"other message", &signature)
+            .is_err());
+
+        let other = InMemorySigner::new(SigningKey::from_bytes(&[8; 32]));
+        assert!(other
+            .verifying_key()
+            .verify(b"message", &signature)
+            .is_err());
+    }
+}
@@ -0,0 +1,1138 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use tokio::io::AsyncWriteExt;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// A license as tracked by the server, alongside the signed token we handed out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LicenseRecord {
+    pub id: String,
+    pub email: String,
+    pub product_id: String,
+    pub plan: String,
+    pub issued_at: String,
+    pub expires_at: Option<String>,
+    pub token: String,
+    /// Tokens this license was signed as before being reissued. They still
+    /// identify it, so its revocation and expiry apply to them too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub superseded_tokens: Vec<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    /// The store's sale id, for licenses issued from a purchase webhook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sale_id: Option<String>,
+    #[serde(default)]
+    pub revoked: bool,
+    #[serde(default)]
+    pub activations: Vec<Activation>,
+    /// Free-text annotation from support; kept in the store, never in the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// The license this one replaced (renewal, upgrade or email change)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_license_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Activation {
+    pub machine_id: String,
+    pub activated_at: String,
+}
+
+/// A pre-generated promo code, redeemable once for a license
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromoCode {
+    /// Uppercase, without separators
+    pub code: String,
+    pub plan: String,
+    /// Length of the license it redeems for; 0 or less means perpetual
+    pub expires_days: i64,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redeemed_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redeemed_by: Option<String>,
+    /// The license issued on redemption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_id: Option<String>,
+}
+
+/// Outcome of `LicenseStore::claim_promo`
+#[derive(Debug)]
+pub enum PromoClaim {
+    Claimed(PromoCode),
+    AlreadyRedeemed,
+    Unknown,
+}
+
+/// Outcome of `LicenseStore::record_activation`
+#[derive(Debug, PartialEq, Eq)]
+pub enum SeatClaim {
+    /// Activated now, or already active on that machine
+    Activated,
+    /// Every seat is held by other machines
+    SeatsExhausted,
+    Unknown,
+}
+
+/// One admin action, as recorded in the append-only audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: String,
+    pub action: String,
+    pub license_id: Option<String>,
+    pub admin: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Storage backend for issued licenses. Handlers only ever talk to this trait,
+/// so a deployment can swap the backend without touching request handling.
+#[async_trait]
+pub trait LicenseStore: Send + Sync {
+    async fn insert(&self, record: LicenseRecord) -> Result<(), String>;
+    async fn get_by_id(&self, id: &str) -> Result<Option<LicenseRecord>, String>;
+    async fn list(&self) -> Result<Vec<LicenseRecord>, String>;
+    /// The license `token` was issued as, current or superseded
+    async fn get_by_token(&self, token: &str) -> Result<Option<LicenseRecord>, String>;
+    /// The license whose token has this support code, uppercase and without
+    /// dashes
+    async fn get_by_support_code(&self, code: &str) -> Result<Option<LicenseRecord>, String>;
+    /// Licenses whose id starts with `prefix`, oldest first. Activation codes
+    /// spell the start of the id.
+    async fn find_by_id_prefix(&self, prefix: &str) -> Result<Vec<LicenseRecord>, String>;
+    /// Licenses issued for a sale, oldest first
+    async fn find_by_sale_id(&self, sale_id: &str) -> Result<Vec<LicenseRecord>, String>;
+    /// Licenses issued to `email`, ignoring case and surrounding whitespace,
+    /// oldest first
+    async fn find_by_email(&self, email: &str) -> Result<Vec<LicenseRecord>, String>;
+    /// Returns `false` if no license with that id exists.
+    async fn revoke(&self, id: &str) -> Result<bool, String>;
+    /// Returns `false` if no license with that id exists.
+    async fn delete(&self, id: &str) -> Result<bool, String>;
+    /// Returns `false` if no license with that id exists.
+    async fn set_expires_at(&self, id: &str, expires_at: Option<String>) -> Result<bool, String>;
+    /// Replace the signed token, e.g. after re-signing it. The old token is
+    /// kept in `superseded_tokens`. Returns `false` if no license with that id
+    /// exists.
+    async fn set_token(&self, id: &str, token: String) -> Result<bool, String>;
+    /// `None` clears the note. Returns `false` if no license with that id exists.
+    async fn set_note(&self, id: &str, note: Option<String>) -> Result<bool, String>;
+    /// Activate a machine, in one step so concurrent activations can't take
+    /// more than `seats` (`None` for no limit). Re-activating the same machine
+    /// is a no-op.
+    async fn record_activation(
+        &self,
+        id: &str,
+        machine_id: &str,
+        seats: Option<u32>,
+    ) -> Result<SeatClaim, String>;
+    async fn insert_promos(&self, promos: Vec<PromoCode>) -> Result<(), String>;
+    /// Mark an unredeemed code as redeemed by `email`, in one step so a code
+    /// can never be redeemed twice.
+    async fn claim_promo(&self, code: &str, email: &str) -> Result<PromoClaim, String>;
+    /// Release a claimed code whose license couldn't be issued, so it can be
+    /// redeemed again. Returns `false` if no such code exists.
+    async fn unclaim_promo(&self, code: &str) -> Result<bool, String>;
+    /// Link a claimed code to the license issued for it. Returns `false` if
+    /// no such code exists.
+    async fn set_promo_license(&self, code: &str, license_id: &str) -> Result<bool, String>;
+    /// Entries are only ever appended, never edited or removed.
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), String>;
+    async fn audit_log(&self) -> Result<Vec<AuditEntry>, String>;
+}
+
+fn sorted(records: &HashMap<String, LicenseRecord>) -> Vec<LicenseRecord> {
+    let mut list: Vec<LicenseRecord> = records.values().cloned().collect();
+    sort_records(&mut list);
+    list
+}
+
+/// Oldest first, the order `LicenseStore::list` returns
+fn sort_records(list: &mut [LicenseRecord]) {
+    list.sort_by(|a, b| a.issued_at.cmp(&b.issued_at).then_with(|| a.id.cmp(&b.id)));
+}
+
+/// The records `matches` picks, oldest first
+fn filtered(
+    records: &HashMap<String, LicenseRecord>,
+    matches: impl Fn(&LicenseRecord) -> bool,
+) -> Vec<LicenseRecord> {
+    let mut list: Vec<LicenseRecord> = records.values().filter(|r| matches(r)).cloned().collect();
+    sort_records(&mut list);
+    list
+}
+
+/// How `find_by_email` compares addresses
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// A token's support code as `get_by_support_code` looks it up
+fn support_code_key(token: &str) -> Option<String> {
+    crate::support_code(token).map(|code| crate::normalize_activation_code(&code))
+}
+
+fn apply_token(record: &mut LicenseRecord, token: String) {
+    let old = std::mem::replace(&mut record.token, token);
+    if old != record.token && !record.superseded_tokens.contains(&old) {
+        record.superseded_tokens.push(old);
+    }
+}
+
+fn apply_activation(record: &mut LicenseRecord, machine_id: &str, seats: Option<u32>) -> SeatClaim {
+    if record
+        .activations
+        .iter()
+        .any(|a| a.machine_id == machine_id)
+    {
+        return SeatClaim::Activated;
+    }
+    if seats.is_some_and(|seats| record.activations.len() >= seats as usize) {
+        return SeatClaim::SeatsExhausted;
+    }
+    record.activations.push(Activation {
+        machine_id: machine_id.to_string(),
+        activated_at: Utc::now().to_rfc3339(),
+    });
+    SeatClaim::Activated
+}
+
+fn apply_claim(promos: &mut HashMap<String, PromoCode>, code: &str, email: &str) -> PromoClaim {
+    match promos.get_mut(code) {
+        Some(promo) => claim(promo, email),
+        None => PromoClaim::Unknown,
+    }
+}
+
+fn claim(promo: &mut PromoCode, email: &str) -> PromoClaim {
+    if promo.redeemed_at.is_some() {
+        return PromoClaim::AlreadyRedeemed;
+    }
+    promo.redeemed_at = Some(Utc::now().to_rfc3339());
+    promo.redeemed_by = Some(email.to_string());
+    PromoClaim::Claimed(promo.clone())
+}
+
+fn unclaim(promo: &mut PromoCode) {
+    promo.redeemed_at = None;
+    promo.redeemed_by = None;
+    promo.license_id = None;
+}
+
+// The SQL stores keep each record, promo code and audit entry as a JSON
+// column next to its key, the same shape `FileStore` writes, so adding a
+// field to a record never needs a schema migration. Licenses also get indexed
+// columns for the fields they are looked up by.
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn to_json<T: Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("Failed to serialize row: {}", e))
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, String> {
+    serde_json::from_str(json).map_err(|e| format!("Corrupt row in store: {}", e))
+}
+
+/// The smallest string above every string that starts with `prefix`; `None`
+/// for an empty prefix, which every string starts with
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        if let Some(next) = char::from_u32(last as u32 + 1) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn db_error(e: sqlx::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+/// Volatile store, used when no `STORE_PATH` is configured.
+#[derive(Default)]
+pub struct MemoryStore {
+    records: RwLock<HashMap<String, LicenseRecord>>,
+    promos: Mutex<HashMap<String, PromoCode>>,
+    audit: Mutex<Vec<AuditEntry>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl LicenseStore for MemoryStore {
+    async fn insert(&self, record: LicenseRecord) -> Result<(), String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        records.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    async fn get_by_id(&self, id: &str) -> Result<Option<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(records.get(id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(sorted(&records))
+    }
+
+    async fn get_by_token(&self, token: &str) -> Result<Option<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(records
+            .values()
+            .find(|r| r.token == token || r.superseded_tokens.iter().any(|t| t == token))
+            .cloned())
+    }
+
+    async fn get_by_support_code(&self, code: &str) -> Result<Option<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(records
+            .values()
+            .find(|r| support_code_key(&r.token).as_deref() == Some(code))
+            .cloned())
+    }
+
+    async fn find_by_id_prefix(&self, prefix: &str) -> Result<Vec<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(filtered(&records, |r| r.id.starts_with(prefix)))
+    }
+
+    async fn find_by_sale_id(&self, sale_id: &str) -> Result<Vec<LicenseRecord>, String> {
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(filtered(&records, |r| {
+            r.sale_id.as_deref() == Some(sale_id)
+        }))
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<LicenseRecord>, String> {
+        let email = normalize_email(email);
+        let records = self
+            .records
+            .read()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(filtered(&records, |r| normalize_email(&r.email) == email))
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match records.get_mut(id) {
+            Some(record) => {
+                record.revoked = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(records.remove(id).is_some())
+    }
+
+    async fn set_expires_at(&self, id: &str, expires_at: Option<String>) -> Result<bool, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match records.get_mut(id) {
+            Some(record) => {
+                record.expires_at = expires_at;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_token(&self, id: &str, token: String) -> Result<bool, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match records.get_mut(id) {
+            Some(record) => {
+                apply_token(record, token);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_note(&self, id: &str, note: Option<String>) -> Result<bool, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match records.get_mut(id) {
+            Some(record) => {
+                record.note = note;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn record_activation(
+        &self,
+        id: &str,
+        machine_id: &str,
+        seats: Option<u32>,
+    ) -> Result<SeatClaim, String> {
+        let mut records = self
+            .records
+            .write()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match records.get_mut(id) {
+            Some(record) => Ok(apply_activation(record, machine_id, seats)),
+            None => Ok(SeatClaim::Unknown),
+        }
+    }
+
+    async fn insert_promos(&self, new_promos: Vec<PromoCode>) -> Result<(), String> {
+        let mut promos = self
+            .promos
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        promos.extend(new_promos.into_iter().map(|p| (p.code.clone(), p)));
+        Ok(())
+    }
+
+    async fn claim_promo(&self, code: &str, email: &str) -> Result<PromoClaim, String> {
+        let mut promos = self
+            .promos
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(apply_claim(&mut promos, code, email))
+    }
+
+    async fn unclaim_promo(&self, code: &str) -> Result<bool, String> {
+        let mut promos = self
+            .promos
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match promos.get_mut(code) {
+            Some(promo) => {
+                unclaim(promo);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_promo_license(&self, code: &str, license_id: &str) -> Result<bool, String> {
+        let mut promos = self
+            .promos
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        match promos.get_mut(code) {
+            Some(promo) => {
+                promo.license_id = Some(license_id.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), String> {
+        let mut audit = self
+            .audit
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        audit.push(entry);
+        Ok(())
+    }
+
+    async fn audit_log(&self) -> Result<Vec<AuditEntry>, String> {
+        let audit = self
+            .audit
+            .lock()
+            .map_err(|_| "Store lock poisoned".to_string())?;
+        Ok(audit.clone())
+    }
+}
+
+/// Single-node persistent store: the whole table is kept in memory and
+/// rewritten to a JSON file (via temp file + rename) after every change. A
+/// change is written from a copy first and only then swapped in, so a failed
+/// write leaves memory matching the file. Promo codes are kept the same way in
+/// a file next to it, and the audit log as JSON lines that are only appended
+/// to. File I/O goes through `tokio::fs`, off the async workers.
+pub struct FileStore {
+    path: PathBuf,
+    promos_path: PathBuf,
+    audit_path: PathBuf,
+    records: tokio::sync::RwLock<HashMap<String, LicenseRecord>>,
+    promos: tokio::sync::Mutex<HashMap<String, PromoCode>>,
+    audit_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let records = if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read store {}: {}", path.display(), e))?;
+            let list: Vec<LicenseRecord> = serde_json::from_str(&contents)
+                .map_err(|e| format!("Failed to parse store {}: {}", path.display(), e))?;
+            list.into_iter().map(|r| (r.id.clone(), r)).collect()
+        } else {
+            HashMap::new()
+        };
+
+        let promos_path = path.with_extension("promos.json");
+        let promos = if promos_path.exists() {
+            let contents = std::fs::read_to_string(&promos_path).map_err(|e| {
+                format!(
+                    "Failed to read promo codes {}: {}",
+                    promos_path.display(),
+                    e
+                )
+            })?;
+            let list: Vec<PromoCode> = serde_json::from_str(&contents).map_err(|e| {
+                format!(
+                    "Failed to parse promo codes {}: {}",
+                    promos_path.display(),
+                    e
+                )
+            })?;
+            list.into_iter().map(|p| (p.code.clone(), p)).collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            audit_path: path.with_extension("audit.jsonl"),
+            promos_path,
+            path,
+            records: tokio::sync::RwLock::new(records),
+            promos: tokio::sync::Mutex::new(promos),
+            audit_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Apply `change` to a copy of the records and, if it reports that it
+    /// changed something, write the copy out before swapping it in
+    async fn update_records<T: Send>(
+        &self,
+        change: impl FnOnce(&mut HashMap<String, LicenseRecord>) -> (T, bool) + Send,
+    ) -> Result<T, String> {
+        let mut records = self.records.write().await;
+        let mut updated = records.clone();
+        let (result, changed) = change(&mut updated);
+        if changed {
+            let json = serde_json::to_string_pretty(&sorted(&updated))
+                .map_err(|e| format!("Failed to serialize store: {}", e))?;
+            write_atomic(&self.path, json)
+                .await
+                .map_err(|e| format!("Failed to write store: {}", e))?;
+            *records = updated;
+        }
+        Ok(result)
+    }
+
+    /// `update_records` for the record with `id`. Returns `false` if there is
+    /// none.
+    async fn update_record(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut LicenseRecord) + Send,
+    ) -> Result<bool, String> {
+        self.update_records(|records| match records.get_mut(id) {
+            Some(record) => {
+                update(record);
+                (true, true)
+            }
+            None => (false, false),
+        })
+        .await
+    }
+
+    /// `update_records` for the promo codes
+    async fn update_promos<T: Send>(
+        &self,
+        change: impl FnOnce(&mut HashMap<String, PromoCode>) -> (T, bool) + Send,
+    ) -> Result<T, String> {
+        let mut promos = self.promos.lock().await;
+        let mut updated = promos.clone();
+        let (result, changed) = change(&mut updated);
+        if changed {
+            let mut list: Vec<&PromoCode> = updated.values().collect();
+            list.sort_by(|a, b| {
+                a.created_at
+                    .cmp(&b.created_at)
+                    .then_with(|| a.code.cmp(&b.code))
+            });
+            let json = serde_json::to_string_pretty(&list)
+                .map_err(|e| format!("Failed to serialize promo codes: {}", e))?;
+            write_atomic(&self.promos_path, json)
+                .await
+                .map_err(|e| format!("Failed to write promo codes: {}", e))?;
+            *promos = updated;
+        }
+        Ok(result)
+    }
+}
+
+/// Write `contents` to a temp file next to `path`, then rename it over `path`
+async fn write_atomic(path: &Path, contents: String) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, contents).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[async_trait]
+impl LicenseStore for FileStore {
+    async fn insert(&self, record: LicenseRecord) -> Result<(), String> {
+        self.update_records(|records| {
+            records.insert(record.id.clone(), record);
+            ((), true)
+        })
+        .await
+    }
+
+    async fn get_by_id(&self, id: &str) -> Result<Option<LicenseRecord>, String> {
+        Ok(self.records.read().await.get(id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<LicenseRecord>, String> {
+        Ok(sorted(&*self.records.read().await))
+    }
+
+    async fn get_by_token(&self, token: &str) -> Result<Option<LicenseRecord>, String> {
+        Ok(self
+            .records
+            .read()
+            .await
+            .values()
+            .find(|r| r.token == token || r.superseded_tokens.iter().any(|t| t == token))
+            .cloned())
+    }
+
+    async fn get_by_support_code(&self, code: &str) -> Result<Option<LicenseRecord>, String> {
+        Ok(self
+            .records
+            .read()
+            .await
+            .values()
+            .find(|r| support_code_key(&r.token).as_deref() == Some(code))
+            .cloned())
+    }
+
+    async fn find_by_id_prefix(&self, prefix: &str) -> Result<Vec<LicenseRecord>, String> {
+        let records = self.records.read().await;
+        Ok(filtered(&records, |r| r.id.starts_with(prefix)))
+    }
+
+    async fn find_by_sale_id(&self, sale_id: &str) -> Result<Vec<LicenseRecord>, String> {
+        let records = self.records.read().await;
+        Ok(filtered(&records, |r| {
+            r.sale_id.as_deref() == Some(sale_id)
+        }))
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<LicenseRecord>, String> {
+        let email = normalize_email(email);
+        let records = self.records.read().await;
+        Ok(filtered(&records, |r| normalize_email(&r.email) == email))
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, String> {
+        self.update_record(id, |record| record.revoked = true).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, String> {
+        self.update_records(|records| {
+            let removed = records.remove(id).is_some();
+            (removed, removed)
+        })
+        .await
+    }
+
+    async fn set_expires_at(&self, id: &str, expires_at: Option<String>) -> Result<bool, String> {
+        self.update_record(id, |record| record.expires_at = expires_at)
+            .await
+    }
+
+    async fn set_token(&self, id: &str, token: String) -> Result<bool, String> {
+        self.update_record(id, |record| apply_token(record, token))
+            .await
+    }
+
+    async fn set_note(&self, id: &str, note: Option<String>) -> Result<bool, String> {
+        self.update_record(id, |record| record.note = note).await
+    }
+
+    async fn record_activation(
+        &self,
+        id: &str,
+        machine_id: &str,
+        seats: Option<u32>,
+    ) -> Result<SeatClaim, String> {
+        self.update_records(|records| match records.get_mut(id) {
+            Some(record) => {
+                let claim = apply_activation(record, machine_id, seats);
+                let changed = claim == SeatClaim::Activated;
+                (claim, changed)
+            }
+            None => (SeatClaim::Unknown, false),
+        })
+        .await
+    }
+
+    async fn insert_promos(&self, new_promos: Vec<PromoCode>) -> Result<(), String> {
+        self.update_promos(|promos| {
+            promos.extend(new_promos.into_iter().map(|p| (p.code.clone(), p)));
+            ((), true)
+        })
+        .await
+    }
+
+    async fn claim_promo(&self, code: &str, email: &str) -> Result<PromoClaim, String> {
+        self.update_promos(|promos| {
+            let claim = apply_claim(promos, code, email);
+            let changed = matches!(claim, PromoClaim::Claimed(_));
+            (claim, changed)
+        })
+        .await
+    }
+
+    async fn unclaim_promo(&self, code: &str) -> Result<bool, String> {
+        self.update_promos(|promos| match promos.get_mut(code) {
+            Some(promo) => {
+                unclaim(promo);
+                (true, true)
+            }
+            None => (false, false),
+        })
+        .await
+    }
+
+    async fn set_promo_license(&self, code: &str, license_id: &str) -> Result<bool, String> {
+        self.update_promos(|promos| match promos.get_mut(code) {
+            Some(promo) => {
+                promo.license_id = Some(license_id.to_string());
+                (true, true)
+            }
+            None => (false, false),
+        })
+        .await
+    }
+
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), String> {
+        let line = serde_json::to_string(&entry)
+            .map_err(|e| format!("Failed to serialize audit entry: {}", e))?;
+        let _guard = self.audit_lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_path)
+            .await
+            .map_err(|e| format!("Failed to open audit log: {}", e))?;
+        file.write_all(format!("{}\n", line).as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write audit log: {}", e))
+    }
+
+    async fn audit_log(&self) -> Result<Vec<AuditEntry>, String> {
+        let _guard = self.audit_lock.lock().await;
+        let contents = match tokio::fs::read_to_string(&self.audit_path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read audit log: {}", e)),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| format!("Corrupt audit log entry: {}", e))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+
+    fn record(id: &str, issued_at: &str) -> LicenseRecord {
+        LicenseRecord {
+            id: id.to_string(),
+            email: format!("{}@example.com", id),
+            product_id: "localendar-mvp".to_string(),
+            plan: "pro".to_string(),
+            issued_at: issued_at.to_string(),
+            expires_at: None,
+            // Shaped like a token, so it has a support code
+            token: format!("payload-{}.{}", id, general_purpose::STANDARD.encode(id)),
+            superseded_tokens: Vec::new(),
+            source: Some("admin".to_string()),
+            sale_id: None,
+            revoked: false,
+            activations: Vec::new(),
+            note: None,
+            previous_license_id: None,
+        }
+    }
+
+    fn promo(code: &str) -> PromoCode {
+        PromoCode {
+            code: code.to_string(),
+            plan: "pro".to_string(),
+            expires_days: 365,
+            created_at: "2025-01-01T00:00:00+00:00".to_string(),
+            redeemed_at: None,
+            redeemed_by: None,
+            license_id: None,
+        }
+    }
+
+    fn audit_entry(action: &str, license_id: &str) -> AuditEntry {
+        AuditEntry {
+            at: Utc::now().to_rfc3339(),
+            action: action.to_string(),
+            license_id: Some(license_id.to_string()),
+            admin: "admin".to_string(),
+            detail: None,
+        }
+    }
+
+    /// What every backend must do the same way. Ids and codes start with
+    /// `run` so the suite can share a database with earlier runs.
+    async fn exercise(store: &dyn LicenseStore, run: &str) {
+        let newer = format!("{}-b", run);
+        let older = format!("{}-a", run);
+        let mut sold = record(&newer, "2025-06-01T00:00:00+00:00");
+        sold.sale_id = Some(format!("{}-sale", run));
+        store.insert(sold).await.unwrap();
+        store
+            .insert(record(&older, "2025-01-01T00:00:00+00:00"))
+            .await
+            .unwrap();
+
+        let fetched = store.get_by_id(&older).await.unwrap().unwrap();
+        assert_eq!(fetched.email, format!("{}@example.com", older));
+        assert!(store
+            .get_by_id(&format!("{}-missing", run))
+            .await
+            .unwrap()
+            .is_none());
+
+        let ids: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .filter(|id| id.starts_with(run))
+            .collect();
+        assert_eq!(ids, vec![older.clone(), newer.clone()]);
+
+        let ids = |records: Vec<LicenseRecord>| -> Vec<String> {
+            records.into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(
+            ids(store.find_by_id_prefix(run).await.unwrap()),
+            vec![older.clone(), newer.clone()]
+        );
+        assert_eq!(
+            ids(store.find_by_id_prefix(&newer).await.unwrap()),
+            vec![newer.clone()]
+        );
+        assert_eq!(
+            ids(store
+                .find_by_sale_id(&format!("{}-sale", run))
+                .await
+                .unwrap()),
+            vec![newer.clone()]
+        );
+        assert!(store
+            .find_by_sale_id(&format!("{}-other", run))
+            .await
+            .unwrap()
+            .is_empty());
+        let email = format!(" {}@Example.com ", older.to_uppercase());
+        assert_eq!(
+            ids(store.find_by_email(&email).await.unwrap()),
+            vec![older.clone()]
+        );
+        assert_eq!(
+            store
+                .get_by_token(&fetched.token)
+                .await
+                .unwrap()
+                .unwrap()
+                .id,
+            older
+        );
+        assert!(store
+            .get_by_token(&format!("{}-missing", run))
+            .await
+            .unwrap()
+            .is_none());
+        let code = support_code_key(&fetched.token).unwrap();
+        assert_eq!(
+            store.get_by_support_code(&code).await.unwrap().unwrap().id,
+            older
+        );
+
+        assert!(store.revoke(&older).await.unwrap());
+        assert!(!store.revoke(&format!("{}-missing", run)).await.unwrap());
+        assert!(store.get_by_id(&older).await.unwrap().unwrap().revoked);
+
+        let activate =
+            |machine_id: &'static str| store.record_activation(&newer, machine_id, Some(2));
+        assert_eq!(activate("machine-1").await.unwrap(), SeatClaim::Activated);
+        assert_eq!(activate("machine-1").await.unwrap(), SeatClaim::Activated);
+        assert_eq!(activate("machine-2").await.unwrap(), SeatClaim::Activated);
+        // Both seats are taken, but the machines holding them can re-activate
+        assert_eq!(
+            activate("machine-3").await.unwrap(),
+            SeatClaim::SeatsExhausted
+        );
+        assert_eq!(activate("machine-2").await.unwrap(), SeatClaim::Activated);
+        assert_eq!(
+            store
+                .record_activation(&format!("{}-missing", run), "machine-1", None)
+                .await
+                .unwrap(),
+            SeatClaim::Unknown
+        );
+        let machines: Vec<String> = store
+            .get_by_id(&newer)
+            .await
+            .unwrap()
+            .unwrap()
+            .activations
+            .into_iter()
+            .map(|a| a.machine_id)
+            .collect();
+        assert_eq!(machines, vec!["machine-1", "machine-2"]);
+
+        assert!(store
+            .set_note(&newer, Some("VIP".to_string()))
+            .await
+            .unwrap());
+        assert!(store
+            .set_expires_at(&newer, Some("2030-01-01T00:00:00+00:00".to_string()))
+            .await
+            .unwrap());
+        let resigned = format!("{}-re-signed", run);
+        assert!(store.set_token(&newer, resigned.clone()).await.unwrap());
+        let updated = store.get_by_id(&newer).await.unwrap().unwrap();
+        assert_eq!(
+            store.get_by_token(&resigned).await.unwrap().unwrap().id,
+            newer
+        );
+        // The token it replaced still finds the license
+        assert_eq!(updated.superseded_tokens.len(), 1);
+        assert_eq!(
+            store
+                .get_by_token(&updated.superseded_tokens[0])
+                .await
+                .unwrap()
+                .unwrap()
+                .id,
+            newer
+        );
+        assert_eq!(updated.note.as_deref(), Some("VIP"));
+        assert_eq!(
+            updated.expires_at.as_deref(),
+            Some("2030-01-01T00:00:00+00:00")
+        );
+        assert_eq!(updated.token, resigned);
+        // Updates keep what earlier updates wrote
+        assert_eq!(updated.activations.len(), 2);
+        assert!(store.set_note(&newer, None).await.unwrap());
+        assert!(store
+            .get_by_id(&newer)
+            .await
+            .unwrap()
+            .unwrap()
+            .note
+            .is_none());
+
+        assert!(store.delete(&older).await.unwrap());
+        assert!(!store.delete(&older).await.unwrap());
+        assert!(store.get_by_id(&older).await.unwrap().is_none());
+
+        let code = format!("{}PROMO", run.to_uppercase());
+        store.insert_promos(vec![promo(&code)]).await.unwrap();
+        match store.claim_promo(&code, "buyer@example.com").await.unwrap() {
+            PromoClaim::Claimed(claimed) => {
+                assert_eq!(claimed.redeemed_by.as_deref(), Some("buyer@example.com"));
+                assert!(claimed.redeemed_at.is_some());
+            }
+            other => panic!("expected a claim, got {:?}", other),
+        }
+        assert!(matches!(
+            store.claim_promo(&code, "other@example.com").await.unwrap(),
+            PromoClaim::AlreadyRedeemed
+        ));
+        assert!(matches!(
+            store
+                .claim_promo(&format!("{}NOPE", code), "buyer@example.com")
+                .await
+                .unwrap(),
+            PromoClaim::Unknown
+        ));
+        // Released, the code can be claimed again
+        assert!(store.unclaim_promo(&code).await.unwrap());
+        assert!(!store.unclaim_promo(&format!("{}NOPE", code)).await.unwrap());
+        assert!(matches!(
+            store.claim_promo(&code, "other@example.com").await.unwrap(),
+            PromoClaim::Claimed(claimed) if claimed.redeemed_by.as_deref() == Some("other@example.com")
+        ));
+        assert!(store.set_promo_license(&code, &newer).await.unwrap());
+        assert!(!store
+            .set_promo_license(&format!("{}NOPE", code), &newer)
+            .await
+            .unwrap());
+
+        store
+            .append_audit(audit_entry("generate", &newer))
+            .await
+            .unwrap();
+        store
+            .append_audit(audit_entry("revoke", &older))
+            .await
+            .unwrap();
+        let log = store.audit_log().await.unwrap();
+        let tail: Vec<(&str, Option<&str>)> = log[log.len() - 2..]
+            .iter()
+            .map(|e| (e.action.as_str(), e.license_id.as_deref()))
+            .collect();
+        assert_eq!(
+            tail,
+            vec![
+                ("generate", Some(newer.as_str())),
+                ("revoke", Some(older.as_str()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_store_behaves_like_a_store() {
+        exercise(&MemoryStore::new(), "mem").await;
+    }
+
+    #[tokio::test]
+    async fn file_store_behaves_like_a_store_and_survives_reopening() {
+        let dir =
+            std::env::temp_dir().join(format!("license-store-test-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("licenses.json");
+
+        exercise(&FileStore::open(&path).unwrap(), "file").await;
+
+        let reopened = FileStore::open(&path).unwrap();
+        let record = reopened.get_by_id("file-b").await.unwrap().unwrap();
+        assert_eq!(record.note, None);
+        assert_eq!(record.activations.len(), 2);
+        assert!(reopened.get_by_id("file-a").await.unwrap().is_none());
+        assert!(matches!(
+            reopened
+                .claim_promo("FILEPROMO", "x@example.com")
+                .await
+                .unwrap(),
+            PromoClaim::AlreadyRedeemed
+        ));
+        assert_eq!(reopened.audit_log().await.unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_store_keeps_memory_unchanged_when_a_write_fails() {
+        let dir =
+            std::env::temp_dir().join(format!("license-store-test-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("licenses.json");
+        let store = FileStore::open(&path).unwrap();
+        store
+            .insert(record("kept", "2024-01-01T00:00:00Z"))
+            .await
+            .unwrap();
+
+        // A directory where the temp file should go makes the next write fail
+        std::fs::create_dir_all(path.with_extension("tmp")).unwrap();
+        assert!(store.revoke("kept").await.is_err());
+        assert!(store
+            .insert(record("lost", "2024-01-02T00:00:00Z"))
+            .await
+            .is_err());
+
+        assert!(!store.get_by_id("kept").await.unwrap().unwrap().revoked);
+        assert!(store.get_by_id("lost").await.unwrap().is_none());
+        let reopened = FileStore::open(&path).unwrap();
+        assert_eq!(reopened.list().await.unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_store_behaves_like_a_store() {
+        let store = SqliteStore::connect("sqlite::memory:").await.unwrap();
+        exercise(&store, "sqlite").await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn postgres_store_behaves_like_a_store() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+        let store = PostgresStore::connect(&url).await.unwrap();
+        exercise(&store, &format!("pg{}", rand::random::<u32>())).await;
+    }
+}
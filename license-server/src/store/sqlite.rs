@@ -0,0 +1,353 @@
+use super::{
+    apply_activation, apply_token, claim, db_error, from_json, normalize_email, prefix_end,
+    sort_records, support_code_key, to_json, unclaim, AuditEntry, LicenseRecord, LicenseStore,
+    PromoClaim, PromoCode, SeatClaim,
+};
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{Sqlite, Transaction};
+use std::str::FromStr;
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS licenses (id TEXT PRIMARY KEY, record TEXT NOT NULL, \
+     token TEXT NOT NULL, support_code TEXT, sale_id TEXT, email TEXT NOT NULL)",
+    "CREATE INDEX IF NOT EXISTS licenses_token ON licenses (token)",
+    "CREATE INDEX IF NOT EXISTS licenses_support_code ON licenses (support_code)",
+    "CREATE INDEX IF NOT EXISTS licenses_sale_id ON licenses (sale_id)",
+    "CREATE INDEX IF NOT EXISTS licenses_email ON licenses (email)",
+    "CREATE TABLE IF NOT EXISTS superseded_tokens (token TEXT PRIMARY KEY, license_id TEXT NOT NULL)",
+    "CREATE TABLE IF NOT EXISTS promo_codes (code TEXT PRIMARY KEY, promo TEXT NOT NULL)",
+    "CREATE TABLE IF NOT EXISTS audit_log (seq INTEGER PRIMARY KEY AUTOINCREMENT, entry TEXT NOT NULL)",
+];
+
+/// Single-node store in a SQLite database. The pool holds one connection, so
+/// every read-modify-write runs on its own and no update is lost.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open (creating if needed) the database at `url`, e.g.
+    /// `sqlite://licenses.db` or `sqlite::memory:`
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let options = SqliteConnectOptions::from_str(url)
+            .map_err(db_error)?
+            .create_if_missing(true);
+        // An in-memory database lives and dies with its connection, so the
+        // one connection is never closed for being idle or old
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .map_err(db_error)?;
+        for statement in SCHEMA {
+            sqlx::query(statement)
+                .execute(&pool)
+                .await
+                .map_err(db_error)?;
+        }
+        Ok(Self { pool })
+    }
+
+    async fn update_record(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut LicenseRecord) + Send,
+    ) -> Result<bool, String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let row: Option<(String,)> = sqlx::query_as("SELECT record FROM licenses WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let Some((json,)) = row else {
+            return Ok(false);
+        };
+        let mut record: LicenseRecord = from_json(&json)?;
+        update(&mut record);
+        sqlx::query("UPDATE licenses SET record = ?, token = ?, support_code = ? WHERE id = ?")
+            .bind(to_json(&record)?)
+            .bind(&record.token)
+            .bind(support_code_key(&record.token))
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        index_superseded_tokens(&mut tx, &record).await?;
+        tx.commit().await.map_err(db_error)?;
+        Ok(true)
+    }
+
+    /// Apply `update` to a promo code and save it, in one transaction.
+    /// Returns `false` if no such code exists.
+    async fn update_promo(
+        &self,
+        code: &str,
+        update: impl FnOnce(&mut PromoCode) + Send,
+    ) -> Result<bool, String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let row: Option<(String,)> = sqlx::query_as("SELECT promo FROM promo_codes WHERE code = ?")
+            .bind(code)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let Some((json,)) = row else {
+            return Ok(false);
+        };
+        let mut promo: PromoCode = from_json(&json)?;
+        update(&mut promo);
+        sqlx::query("UPDATE promo_codes SET promo = ? WHERE code = ?")
+            .bind(to_json(&promo)?)
+            .bind(code)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+        Ok(true)
+    }
+
+    /// Records matching `condition`, oldest first
+    async fn find_where(
+        &self,
+        condition: &str,
+        args: &[&str],
+    ) -> Result<Vec<LicenseRecord>, String> {
+        let sql = format!("SELECT record FROM licenses WHERE {}", condition);
+        let mut query = sqlx::query_as(&sql);
+        for arg in args {
+            query = query.bind(*arg);
+        }
+        let rows: Vec<(String,)> = query.fetch_all(&self.pool).await.map_err(db_error)?;
+        let mut list = rows
+            .iter()
+            .map(|(json,)| from_json(json))
+            .collect::<Result<Vec<LicenseRecord>, String>>()?;
+        sort_records(&mut list);
+        Ok(list)
+    }
+}
+
+/// Point the record's superseded tokens at it, for `get_by_token`
+async fn index_superseded_tokens(
+    tx: &mut Transaction<'_, Sqlite>,
+    record: &LicenseRecord,
+) -> Result<(), String> {
+    for token in &record.superseded_tokens {
+        sqlx::query(
+            "INSERT INTO superseded_tokens (token, license_id) VALUES (?, ?) \
+             ON CONFLICT (token) DO NOTHING",
+        )
+        .bind(token)
+        .bind(&record.id)
+        .execute(&mut **tx)
+        .await
+        .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl LicenseStore for SqliteStore {
+    async fn insert(&self, record: LicenseRecord) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        sqlx::query(
+            "INSERT INTO licenses (id, record, token, support_code, sale_id, email) \
+             VALUES (?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (id) DO UPDATE SET record = excluded.record, token = excluded.token, \
+             support_code = excluded.support_code, sale_id = excluded.sale_id, email = excluded.email",
+        )
+        .bind(&record.id)
+        .bind(to_json(&record)?)
+        .bind(&record.token)
+        .bind(support_code_key(&record.token))
+        .bind(&record.sale_id)
+        .bind(normalize_email(&record.email))
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+        index_superseded_tokens(&mut tx, &record).await?;
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn get_by_id(&self, id: &str) -> Result<Option<LicenseRecord>, String> {
+        let row: Option<(String,)> = sqlx::query_as("SELECT record FROM licenses WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)?;
+        row.map(|(json,)| from_json(&json)).transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<LicenseRecord>, String> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT record FROM licenses")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)?;
+        let mut list = rows
+            .iter()
+            .map(|(json,)| from_json(json))
+            .collect::<Result<Vec<LicenseRecord>, String>>()?;
+        sort_records(&mut list);
+        Ok(list)
+    }
+
+    async fn get_by_token(&self, token: &str) -> Result<Option<LicenseRecord>, String> {
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT record FROM licenses WHERE token = ?1 \
+             OR id IN (SELECT license_id FROM superseded_tokens WHERE token = ?1)",
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)?;
+        row.map(|(json,)| from_json(&json)).transpose()
+    }
+
+    async fn get_by_support_code(&self, code: &str) -> Result<Option<LicenseRecord>, String> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT record FROM licenses WHERE support_code = ?")
+                .bind(code)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db_error)?;
+        row.map(|(json,)| from_json(&json)).transpose()
+    }
+
+    async fn find_by_id_prefix(&self, prefix: &str) -> Result<Vec<LicenseRecord>, String> {
+        // Every id starting with `prefix` sorts between it and `prefix` with
+        // its last character bumped, so the primary key index answers this
+        let Some(end) = prefix_end(prefix) else {
+            return self.list().await;
+        };
+        self.find_where("id >= ? AND id < ?", &[prefix, &end]).await
+    }
+
+    async fn find_by_sale_id(&self, sale_id: &str) -> Result<Vec<LicenseRecord>, String> {
+        self.find_where("sale_id = ?", &[sale_id]).await
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Vec<LicenseRecord>, String> {
+        self.find_where("email = ?", &[&normalize_email(email)])
+            .await
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, String> {
+        self.update_record(id, |record| record.revoked = true).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        sqlx::query("DELETE FROM superseded_tokens WHERE license_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let result = sqlx::query("DELETE FROM licenses WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_expires_at(&self, id: &str, expires_at: Option<String>) -> Result<bool, String> {
+        self.update_record(id, |record| record.expires_at = expires_at)
+            .await
+    }
+
+    async fn set_token(&self, id: &str, token: String) -> Result<bool, String> {
+        self.update_record(id, |record| apply_token(record, token))
+            .await
+    }
+
+    async fn set_note(&self, id: &str, note: Option<String>) -> Result<bool, String> {
+        self.update_record(id, |record| record.note = note).await
+    }
+
+    async fn record_activation(
+        &self,
+        id: &str,
+        machine_id: &str,
+        seats: Option<u32>,
+    ) -> Result<SeatClaim, String> {
+        // Read and written in one transaction, so two machines can't both take
+        // the last seat
+        let mut claim = SeatClaim::Unknown;
+        self.update_record(id, |record| {
+            claim = apply_activation(record, machine_id, seats)
+        })
+        .await?;
+        Ok(claim)
+    }
+
+    async fn insert_promos(&self, promos: Vec<PromoCode>) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        for promo in &promos {
+            sqlx::query(
+                "INSERT INTO promo_codes (code, promo) VALUES (?, ?) \
+                 ON CONFLICT (code) DO UPDATE SET promo = excluded.promo",
+            )
+            .bind(&promo.code)
+            .bind(to_json(promo)?)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn claim_promo(&self, code: &str, email: &str) -> Result<PromoClaim, String> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let row: Option<(String,)> = sqlx::query_as("SELECT promo FROM promo_codes WHERE code = ?")
+            .bind(code)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let Some((json,)) = row else {
+            return Ok(PromoClaim::Unknown);
+        };
+        let mut promo: PromoCode = from_json(&json)?;
+        let claimed = claim(&mut promo, email);
+        if matches!(claimed, PromoClaim::Claimed(_)) {
+            sqlx::query("UPDATE promo_codes SET promo = ? WHERE code = ?")
+                .bind(to_json(&promo)?)
+                .bind(code)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            tx.commit().await.map_err(db_error)?;
+        }
+        Ok(claimed)
+    }
+
+    async fn unclaim_promo(&self, code: &str) -> Result<bool, String> {
+        self.update_promo(code, unclaim).await
+    }
+
+    async fn set_promo_license(&self, code: &str, license_id: &str) -> Result<bool, String> {
+        self.update_promo(code, |promo| {
+            promo.license_id = Some(license_id.to_string())
+        })
+        .await
+    }
+
+    async fn append_audit(&self, entry: AuditEntry) -> Result<(), String> {
+        sqlx::query("INSERT INTO audit_log (entry) VALUES (?)")
+            .bind(to_json(&entry)?)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    async fn audit_log(&self) -> Result<Vec<AuditEntry>, String> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT entry FROM audit_log ORDER BY seq")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)?;
+        rows.iter().map(|(json,)| from_json(json)).collect()
+    }
+}
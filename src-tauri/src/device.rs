@@ -0,0 +1,17 @@
+use sha2::{Digest, Sha256};
+
+/// Stable, anonymous identifier for this machine: a hash of the hostname,
+/// OS and architecture. Used for seat binding; never leaves the device raw.
+pub fn device_fingerprint() -> String {
+    let hostname = gethostname::gethostname().to_string_lossy().to_lowercase();
+    let source = format!(
+        "{}|{}|{}",
+        hostname,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    Sha256::digest(source.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -1,6 +1,12 @@
+mod device;
 mod licensing;
+mod online;
+mod reminder;
+mod storage;
 
 use licensing::{verify_license_token, LicenseStatus};
+use std::collections::{BTreeMap, HashMap};
+use tauri::{Emitter, Manager};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -8,9 +14,537 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/// Licenses bound to a machine only verify on that machine
 #[tauri::command]
 fn verify_license(token: String) -> LicenseStatus {
-    verify_license_token(&token)
+    verify_license_token(&token, &device::device_fingerprint())
+}
+
+#[tauri::command]
+fn verify_with_timing(token: String) -> (LicenseStatus, u128) {
+    licensing::verify_with_timing(&token, &device::device_fingerprint())
+}
+
+/// Like `verify_license`, but domain-scoped evaluation licenses are checked
+/// against the signed-in user's email
+#[tauri::command]
+fn verify_license_for_user(token: String, email: String) -> LicenseStatus {
+    licensing::verify_license_for_user(&token, &device::device_fingerprint(), &email)
+}
+
+#[tauri::command]
+fn verify_requires_plan(token: String, min_plan: String) -> LicenseStatus {
+    licensing::verify_requires_plan(&token, &device::device_fingerprint(), &min_plan)
+}
+
+#[tauri::command]
+fn features_allowed(token: String, features: Vec<String>) -> HashMap<String, bool> {
+    licensing::features_allowed(&token, &device::device_fingerprint(), &features)
+}
+
+#[tauri::command]
+fn missing_features(token: String, all_features: Vec<String>) -> Vec<String> {
+    licensing::missing_features(&token, &device::device_fingerprint(), &all_features)
+}
+
+/// Gate a feature before using it. Denials are also emitted as a
+/// `feature-denied` event carrying the `FeatureDenied`, so one listener can
+/// show the upsell wherever the feature was tried.
+#[tauri::command]
+fn try_use_feature(
+    app: tauri::AppHandle,
+    token: String,
+    feature: String,
+) -> Result<(), licensing::FeatureDenied> {
+    let fingerprint = device::device_fingerprint();
+    licensing::try_use_feature(&token, &fingerprint, &feature).inspect_err(|denied| {
+        let _ = app.emit("feature-denied", denied);
+    })
+}
+
+#[tauri::command]
+fn license_severity(token: String) -> String {
+    licensing::license_severity(&verify_license_token(&token, &device::device_fingerprint()))
+        .as_str()
+        .to_string()
+}
+
+#[tauri::command]
+fn grace_status(token: String) -> licensing::GraceStatus {
+    licensing::grace_status(&token, &device::device_fingerprint())
+}
+
+#[tauri::command]
+fn license_claims(token: String) -> Result<BTreeMap<String, String>, String> {
+    licensing::license_claims(&token, &device::device_fingerprint())
+}
+
+#[tauri::command]
+async fn verify_license_online(
+    app: tauri::AppHandle,
+    token: String,
+    base_url: String,
+) -> Result<LicenseStatus, String> {
+    let (status, server_time) =
+        online::verify_online(&base_url, &token, &device::device_fingerprint()).await?;
+    if let Some(server_time) = server_time {
+        let mut meta = storage::read_meta(&app);
+        meta.observe_server_time(server_time);
+        storage::write_meta(&app, &meta)?;
+    }
+    Ok(status)
+}
+
+/// Verify a token offline, then check it against the server's revocation list
+#[tauri::command]
+async fn verify_license_unless_revoked(
+    token: String,
+    base_url: String,
+) -> Result<LicenseStatus, String> {
+    let revocations = online::fetch_revocations(&base_url).await?;
+    Ok(licensing::verify_license_unless_revoked(
+        &token,
+        &device::device_fingerprint(),
+        &revocations,
+    ))
+}
+
+/// Re-verify the stored license with the server, bypassing any cached result.
+/// Unlike startup verification this never falls back to the cache, so a
+/// "Refresh" button can report that the server couldn't be reached.
+#[tauri::command]
+async fn refresh_license(app: tauri::AppHandle, base_url: String) -> Result<LicenseStatus, String> {
+    let token = storage::read_token(&app)?.ok_or("No license stored")?;
+    let (status, server_time) =
+        online::verify_online(&base_url, &token, &device::device_fingerprint()).await?;
+
+    let mut meta = storage::read_meta(&app);
+    meta.record_verification(&status, server_time);
+    storage::write_meta(&app, &meta)?;
+
+    Ok(status)
+}
+
+/// When the stored license should next be re-verified online, so the
+/// background check and the UI agree. `None` when no license is stored.
+#[tauri::command]
+fn next_reverification_due(app: tauri::AppHandle) -> Option<chrono::DateTime<chrono::Utc>> {
+    let token = storage::read_token(&app).ok().flatten()?;
+    let meta = storage::read_meta(&app);
+    Some(licensing::next_reverification_due(
+        &token,
+        &device::device_fingerprint(),
+        meta.last_verified_at.as_deref(),
+        chrono::Utc::now(),
+    ))
+}
+
+/// A newer, better license for the stored one's customer (e.g. after an
+/// upgrade purchase), if the server has one. The caller decides whether to
+/// `set_license` it.
+#[tauri::command]
+async fn check_license_update(
+    app: tauri::AppHandle,
+    base_url: String,
+) -> Result<Option<String>, String> {
+    let token = storage::read_token(&app)?.ok_or("No license stored")?;
+    let latest = online::fetch_latest_license(&base_url, &token).await?;
+    let fingerprint = device::device_fingerprint();
+    Ok(latest.and_then(|latest| licensing::license_update(&token, &latest, &fingerprint)))
+}
+
+/// Merge `tokens` (e.g. from a messy import) with the stored license, keeping
+/// the best license per email. If one beats the stored license for its email
+/// it replaces it. Returns the licenses kept.
+#[tauri::command]
+fn merge_duplicate_licenses(
+    app: tauri::AppHandle,
+    tokens: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let stored = storage::read_token(&app)?;
+    let all: Vec<String> = stored.iter().cloned().chain(tokens).collect();
+    let fingerprint = device::device_fingerprint();
+    let kept = licensing::merge_duplicate_licenses(&all, &fingerprint);
+
+    let replacement = stored.as_deref().and_then(|stored| {
+        kept.iter()
+            .find_map(|candidate| licensing::license_update(stored, candidate, &fingerprint))
+    });
+    if let Some(replacement) = replacement {
+        set_license(app, replacement)?;
+    }
+    Ok(kept)
+}
+
+/// Whether the server has this machine on the license's activation list.
+/// Answers are cached briefly so the UI can poll without hammering the server.
+#[tauri::command]
+async fn is_this_device_activated(
+    token: String,
+    base_url: String,
+    app: tauri::AppHandle,
+) -> Result<bool, String> {
+    let fingerprint = device::device_fingerprint();
+    // An imported offline activation answers without the network
+    if let Some(response) = storage::read_activation(&app).ok().flatten() {
+        if licensing::verify_activation_response(&response, &token, &fingerprint).is_ok() {
+            return Ok(true);
+        }
+    }
+
+    app.state::<online::ActivationCache>()
+        .is_activated(&base_url, &token, &fingerprint)
+        .await
+}
+
+/// Verify a license and, if it's seat-limited, make sure this machine holds
+/// one of its seats, activating it on first use. Returns an invalid status
+/// when every seat is taken. Licenses without a seat count are only verified.
+/// If the server can't be reached the offline result is returned as is, so
+/// binding is retried on a later launch rather than locking the user out.
+#[tauri::command]
+async fn verify_and_bind(token: String, base_url: String, app: tauri::AppHandle) -> LicenseStatus {
+    let fingerprint = device::device_fingerprint();
+    let status = verify_license_token(&token, &fingerprint);
+    let Some(payload) = status.payload.as_ref().filter(|_| status.valid) else {
+        return status;
+    };
+    if payload.seats.is_some() {
+        if let Some(response) = storage::read_activation(&app).ok().flatten() {
+            if licensing::verify_activation_response(&response, &token, &fingerprint).is_ok() {
+                return status;
+            }
+        }
+    }
+
+    let cache = app.state::<online::ActivationCache>();
+    online::bind_seat(status, &token, &fingerprint, &base_url, &cache).await
+}
+
+/// Seats still free on a team license, for the team admin screen. Uses the
+/// same briefly cached activation list as `is_this_device_activated`.
+#[tauri::command]
+async fn remaining_seats(
+    token: String,
+    base_url: String,
+    app: tauri::AppHandle,
+) -> Result<i64, String> {
+    let activations = app
+        .state::<online::ActivationCache>()
+        .activations(&base_url, &token)
+        .await?;
+    licensing::remaining_seats(&token, &device::device_fingerprint(), activations.len())
+}
+
+/// Activation request code for the stored license on this machine, for an
+/// admin to run through `/offline-activate`
+#[tauri::command]
+fn export_activation_request(app: tauri::AppHandle) -> Result<String, String> {
+    let token = storage::read_token(&app)?.ok_or("No license stored")?;
+    licensing::build_activation_request(&token, &device::device_fingerprint())
+}
+
+/// Import the admin's activation response. It's only saved if it was signed
+/// for the stored license and this machine.
+#[tauri::command]
+fn import_activation_response(
+    app: tauri::AppHandle,
+    response: String,
+) -> Result<licensing::OfflineActivation, String> {
+    let token = storage::read_token(&app)?.ok_or("No license stored")?;
+    let activation =
+        licensing::verify_activation_response(&response, &token, &device::device_fingerprint())?;
+    storage::write_activation(&app, response.trim())?;
+    Ok(activation)
+}
+
+/// The stored license and its metadata as a JSON backup, for moving to
+/// another machine
+#[tauri::command]
+fn export_licensing_backup(app: tauri::AppHandle) -> Result<String, String> {
+    storage::export_backup(&app)
+}
+
+/// Restore a backup from `export_licensing_backup`. Corrupted backups and
+/// invalid licenses are rejected without touching the stored license.
+#[tauri::command]
+fn import_licensing_backup(app: tauri::AppHandle, backup: String) -> Result<LicenseStatus, String> {
+    let status = storage::import_backup(&app, &backup, &device::device_fingerprint())?;
+    reminder::reschedule(&app);
+    Ok(status)
+}
+
+/// Verify a token and, only if it's valid, store it in place of the current
+/// one. An invalid token leaves the stored license untouched.
+#[tauri::command]
+fn set_license(app: tauri::AppHandle, token: String) -> Result<LicenseStatus, String> {
+    let token = licensing::canonicalize_token(&token).unwrap_or(token);
+    let status = storage::save_if_valid(&app, &token, &device::device_fingerprint())?;
+    if status.valid {
+        reminder::reschedule(&app);
+    }
+    Ok(status)
+}
+
+/// Store `token` in place of the current license (atomically) and drop what
+/// was cached about the old one
+fn replace_stored_token(app: &tauri::AppHandle, token: &str) -> Result<(), String> {
+    storage::replace_token(app, token)?;
+    reminder::reschedule(app);
+    Ok(())
+}
+
+/// Store a token without verifying it first, e.g. one the app has just
+/// verified online. `load_license` checks it again when it's read back.
+#[tauri::command]
+fn save_license(app: tauri::AppHandle, token: String) -> Result<(), String> {
+    let token = licensing::canonicalize_token(&token).unwrap_or(token);
+    replace_stored_token(&app, &token)
+}
+
+/// Verify the stored license offline, so startup needs no network call.
+/// `None` when nothing is stored; a file that can't be read or doesn't
+/// verify comes back invalid.
+#[tauri::command]
+fn load_license(app: tauri::AppHandle) -> Option<LicenseStatus> {
+    match storage::read_token(&app) {
+        Ok(token) => token.map(|token| verify_license_token(&token, &device::device_fingerprint())),
+        Err(error) => Some(LicenseStatus::rejected(licensing::LicenseError::Other(
+            error,
+        ))),
+    }
+}
+
+/// (Re)schedule the expiry notification for the stored license. Returns when
+/// it will fire, or nothing for a perpetual or missing license.
+#[tauri::command]
+fn schedule_expiry_reminder(app: tauri::AppHandle) -> Option<String> {
+    reminder::reschedule(&app).map(|at| at.to_rfc3339())
+}
+
+/// Remove all licensing data from this machine, for privacy requests and
+/// clean reinstalls. Safe to call when nothing is stored.
+#[tauri::command]
+fn reset_licensing(app: tauri::AppHandle) -> Result<(), String> {
+    storage::remove_all(&app)?;
+    app.state::<online::ActivationCache>().clear();
+    // With no license stored this just cancels any pending reminder
+    reminder::reschedule(&app);
+    Ok(())
+}
+
+/// Checklist of every requirement the license is held to, for the support
+/// "diagnose my license" button. Uses the last server verdict and device
+/// activation we know of, without going online.
+#[tauri::command]
+fn diagnose_license(app: tauri::AppHandle, token: String) -> Vec<licensing::CheckResult> {
+    let stored = storage::read_token(&app).ok().flatten();
+    let is_stored = stored.as_deref().map(licensing::normalize_token)
+        == Some(licensing::normalize_token(&token));
+    let meta = storage::read_meta(&app);
+    let server = meta.cached_status.filter(|_| is_stored);
+
+    let fingerprint = device::device_fingerprint();
+    let offline_activated = storage::read_activation(&app)
+        .ok()
+        .flatten()
+        .is_some_and(|response| {
+            licensing::verify_activation_response(&response, &token, &fingerprint).is_ok()
+        });
+    let device_activated = if offline_activated {
+        Some(true)
+    } else {
+        app.state::<online::ActivationCache>()
+            .get(&token)
+            .map(|activations| {
+                activations
+                    .iter()
+                    .any(|activation| activation.machine_id == fingerprint)
+            })
+    };
+
+    licensing::diagnose_license(&token, &fingerprint, server.as_ref(), device_activated)
+}
+
+/// Check the stored licensing files for corruption or inconsistencies, for
+/// support to ask users to run
+#[tauri::command]
+fn verify_store_integrity(app: tauri::AppHandle) -> Result<storage::IntegrityReport, String> {
+    storage::check_integrity(&app, &device::device_fingerprint())
+}
+
+#[tauri::command]
+fn licensing_state(app: tauri::AppHandle) -> licensing::LicensingState {
+    let stored = storage::read_token(&app).ok().flatten();
+    let meta = storage::read_meta(&app);
+    licensing::licensing_state(
+        stored.as_deref(),
+        &device::device_fingerprint(),
+        meta.clock_high_water.as_deref(),
+    )
+}
+
+/// Whether the app should allow edits, only viewing, or nothing
+#[tauri::command]
+fn app_access_level(app: tauri::AppHandle) -> licensing::AccessLevel {
+    let stored = storage::read_token(&app).ok().flatten();
+    let meta = storage::read_meta(&app);
+    licensing::access_level(
+        stored.as_deref(),
+        &device::device_fingerprint(),
+        meta.clock_high_water.as_deref(),
+    )
+}
+
+#[tauri::command]
+fn canonicalize_token(token: String) -> Result<String, String> {
+    licensing::canonicalize_token(&token).map_err(|e| e.to_string())
+}
+
+/// Whether the license covers this build of the app
+#[tauri::command]
+fn version_covered(token: String) -> Option<bool> {
+    licensing::version_covered(
+        &token,
+        &device::device_fingerprint(),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Whether the license was issued under terms other than
+/// `current_terms_version`, so the app should ask for re-acceptance
+#[tauri::command]
+fn terms_acceptance_needed(token: String, current_terms_version: String) -> Option<bool> {
+    licensing::terms_acceptance_needed(
+        &token,
+        &device::device_fingerprint(),
+        &current_terms_version,
+    )
+}
+
+/// Verify every license file in a folder, for resellers checking their stock
+#[tauri::command]
+fn verify_license_folder(path: String) -> Result<Vec<(String, LicenseStatus)>, String> {
+    licensing::verify_license_folder(std::path::Path::new(&path), &device::device_fingerprint())
+}
+
+/// Status, plan and expiry of a token, ready to display
+#[tauri::command]
+fn license_view_model(token: String) -> licensing::LicenseViewModel {
+    licensing::license_view_model(
+        &token,
+        &device::device_fingerprint(),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// "Purchased on" date for the license, in local time
+#[tauri::command]
+fn license_issued_local(token: String) -> Option<String> {
+    licensing::license_issued_local(&token, &device::device_fingerprint())
+}
+
+/// Short code identifying the license, for phone support
+#[tauri::command]
+fn license_support_code(token: String) -> Option<String> {
+    licensing::support_code(&token, &device::device_fingerprint())
+}
+
+/// Whether this build can verify licenses offline at all, i.e. ships a real
+/// public key. Lets the UI flag a misconfigured build before offering activation.
+#[tauri::command]
+fn offline_verification_available() -> bool {
+    licensing::offline_verification_available()
+}
+
+/// Anonymous, stable id of a valid license for local analytics
+#[tauri::command]
+fn license_anonymous_id(token: String) -> Option<String> {
+    licensing::anonymous_id(&token, &device::device_fingerprint())
+}
+
+/// Which configured public key verified the token, if any
+#[tauri::command]
+fn matched_key_index(token: String) -> Option<usize> {
+    verify_license_token(&token, &device::device_fingerprint()).matched_key_index
+}
+
+#[tauri::command]
+fn license_deep_link(token: String) -> Result<String, String> {
+    licensing::license_deep_link(&token)
+}
+
+#[tauri::command]
+fn token_from_deep_link(link: String) -> Result<String, String> {
+    licensing::token_from_deep_link(&link)
+}
+
+#[tauri::command]
+fn token_matches_email(token: String, email: String) -> Result<bool, String> {
+    licensing::token_matches_email(&token, &device::device_fingerprint(), &email)
+}
+
+#[tauri::command]
+fn pick_better_license(a: String, b: String) -> Result<String, String> {
+    licensing::pick_better_license(&a, &b, &device::device_fingerprint())
+}
+
+#[tauri::command]
+fn license_differs(app: tauri::AppHandle, token: String) -> bool {
+    let stored = storage::read_token(&app).ok().flatten();
+    licensing::license_differs(stored.as_deref(), &token, &device::device_fingerprint())
+}
+
+#[tauri::command]
+fn export_diagnostics(app: tauri::AppHandle) -> Result<String, String> {
+    let token = storage::read_token(&app)?;
+    let meta = storage::read_meta(&app);
+    let version = app.package_info().version.to_string();
+    let diagnostics = licensing::build_diagnostics(
+        token.as_deref(),
+        &device::device_fingerprint(),
+        meta.last_verified_at,
+        &version,
+    );
+    serde_json::to_string_pretty(&diagnostics)
+        .map_err(|e| format!("Failed to serialize diagnostics: {}", e))
+}
+
+/// Fetch the public key of the build's license server and pin it if none is
+/// pinned yet. Only a bundled key, or one a bundled key endorsed, is pinned;
+/// a key that differs from the pinned one is only accepted with `repin`.
+#[tauri::command]
+async fn pin_server_key(app: tauri::AppHandle, repin: bool) -> Result<String, String> {
+    let fetched = online::fetch_public_key(online::LICENSE_SERVER_URL).await?;
+    let pinned = storage::read_pinned_key(&app)?;
+    let key = licensing::resolve_key_pin(
+        pinned.as_ref(),
+        &fetched.public_key,
+        fetched.endorsement.as_deref(),
+        repin,
+    )?;
+    if pinned.as_ref() != Some(&key) {
+        storage::write_pinned_key(&app, &key)?;
+    }
+    Ok(key.public_key)
+}
+
+/// Verify against the bundled keys plus the pinned server key, if it is
+/// still trusted
+#[tauri::command]
+fn verify_license_pinned(app: tauri::AppHandle, token: String) -> Result<LicenseStatus, String> {
+    let pinned = storage::read_pinned_key(&app)?;
+    Ok(licensing::verify_license_token_pinned(
+        &token,
+        &device::device_fingerprint(),
+        pinned.as_ref(),
+    ))
+}
+
+/// For support: compare against the key the license server signs with
+#[tauri::command]
+fn configured_public_key() -> String {
+    licensing::configured_public_key().to_string()
 }
 
 #[cfg(debug_assertions)]
@@ -19,16 +553,159 @@ fn generate_demo_license(email: String) -> String {
     licensing::generate_demo_license(&email)
 }
 
+/// Verify a token as of an RFC 3339 time, to step through expiry and grace
+/// without changing the system clock
+#[cfg(debug_assertions)]
+#[tauri::command]
+fn verify_license_at(token: String, at: String) -> Result<LicenseStatus, String> {
+    let at = chrono::DateTime::parse_from_rfc3339(&at)
+        .map_err(|e| format!("Invalid time '{}': {}", at, e))?;
+    Ok(licensing::verify_license_token_at(
+        &token,
+        &device::device_fingerprint(),
+        at.with_timezone(&chrono::Utc),
+    ))
+}
+
+/// Replace the stored licensing files so the app lands in `state` (`valid`,
+/// `expired`, `grace`, `tampered` or `none`), for manual UI testing
+#[cfg(debug_assertions)]
+#[tauri::command]
+fn debug_set_state(app: tauri::AppHandle, state: String) -> Result<(), String> {
+    let token = licensing::debug_state_token(&state)?;
+    storage::remove_all(&app)?;
+    if let Some(token) = token {
+        storage::write_token(&app, &token)?;
+    }
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     #[cfg(debug_assertions)]
-    let handler = tauri::generate_handler![greet, verify_license, generate_demo_license];
+    let handler = tauri::generate_handler![
+        greet,
+        verify_license,
+        verify_license_for_user,
+        verify_with_timing,
+        verify_license_folder,
+        matched_key_index,
+        offline_verification_available,
+        verify_requires_plan,
+        features_allowed,
+        missing_features,
+        try_use_feature,
+        license_severity,
+        grace_status,
+        license_claims,
+        license_view_model,
+        license_issued_local,
+        license_support_code,
+        license_anonymous_id,
+        verify_license_online,
+        verify_license_unless_revoked,
+        refresh_license,
+        next_reverification_due,
+        check_license_update,
+        merge_duplicate_licenses,
+        is_this_device_activated,
+        remaining_seats,
+        verify_and_bind,
+        export_activation_request,
+        import_activation_response,
+        export_licensing_backup,
+        import_licensing_backup,
+        set_license,
+        save_license,
+        load_license,
+        schedule_expiry_reminder,
+        licensing_state,
+        app_access_level,
+        diagnose_license,
+        verify_store_integrity,
+        canonicalize_token,
+        license_deep_link,
+        token_from_deep_link,
+        reset_licensing,
+        license_differs,
+        pick_better_license,
+        token_matches_email,
+        version_covered,
+        terms_acceptance_needed,
+        export_diagnostics,
+        configured_public_key,
+        pin_server_key,
+        verify_license_pinned,
+        generate_demo_license,
+        debug_set_state,
+        verify_license_at
+    ];
     
     #[cfg(not(debug_assertions))]
-    let handler = tauri::generate_handler![greet, verify_license];
+    let handler = tauri::generate_handler![
+        greet,
+        verify_license,
+        verify_license_for_user,
+        verify_with_timing,
+        verify_license_folder,
+        matched_key_index,
+        offline_verification_available,
+        verify_requires_plan,
+        features_allowed,
+        missing_features,
+        try_use_feature,
+        license_severity,
+        grace_status,
+        license_claims,
+        license_view_model,
+        license_issued_local,
+        license_support_code,
+        license_anonymous_id,
+        verify_license_online,
+        verify_license_unless_revoked,
+        refresh_license,
+        next_reverification_due,
+        check_license_update,
+        merge_duplicate_licenses,
+        is_this_device_activated,
+        remaining_seats,
+        verify_and_bind,
+        export_activation_request,
+        import_activation_response,
+        export_licensing_backup,
+        import_licensing_backup,
+        set_license,
+        save_license,
+        load_license,
+        schedule_expiry_reminder,
+        licensing_state,
+        app_access_level,
+        diagnose_license,
+        verify_store_integrity,
+        canonicalize_token,
+        license_deep_link,
+        token_from_deep_link,
+        reset_licensing,
+        license_differs,
+        pick_better_license,
+        token_matches_email,
+        version_covered,
+        terms_acceptance_needed,
+        export_diagnostics,
+        configured_public_key,
+        pin_server_key,
+        verify_license_pinned
+    ];
     
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_notification::init())
+        .manage(online::ActivationCache::default())
+        .manage(reminder::ExpiryReminder::default())
+        .setup(|app| {
+            reminder::reschedule(app.handle());
+            Ok(())
+        })
         .invoke_handler(handler)
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,19 +1,130 @@
 use base64::{engine::general_purpose, Engine as _};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, TimeZone, Utc};
 use ed25519_dalek::{Signature, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-// Public key for license verification (in production, this would be your actual public key)
-// For now, using a placeholder - replace with your actual Ed25519 public key
-const PUBLIC_KEY_BASE64: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+// Public key for license verification, baked in at compile time from
+// `LOCALENDAR_PUBLIC_KEY` (printed by the server's `keygen`). Release builds
+// refuse to compile without it; debug builds fall back to an all-zero
+// placeholder so `cargo test` runs without a key.
+#[cfg(not(debug_assertions))]
+const PUBLIC_KEY_BASE64: &str = env!(
+    "LOCALENDAR_PUBLIC_KEY",
+    "LOCALENDAR_PUBLIC_KEY must be set for release builds: run `cargo run --bin keygen` in license-server and export the line it prints"
+);
+#[cfg(debug_assertions)]
+const PUBLIC_KEY_BASE64: &str = public_key_or_placeholder(option_env!("LOCALENDAR_PUBLIC_KEY"));
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// What a debug build without `LOCALENDAR_PUBLIC_KEY` verifies against
+#[cfg(debug_assertions)]
+const PLACEHOLDER_PUBLIC_KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+#[cfg(debug_assertions)]
+const fn public_key_or_placeholder(configured: Option<&'static str>) -> &'static str {
+    match configured {
+        Some(key) => key,
+        None => PLACEHOLDER_PUBLIC_KEY,
+    }
+}
+
+// Every key this build accepts. When the signing key is rotated, add the new
+// key here and keep the old one until its licenses have been reissued. Tokens
+// that carry a `kid` are checked against the key with that id only; older
+// tokens are tried against each key in turn.
+#[cfg(not(debug_assertions))]
+const PUBLIC_KEYS: &[&str] = &[PUBLIC_KEY_BASE64];
+#[cfg(debug_assertions)]
+const PUBLIC_KEYS: &[&str] = &[PUBLIC_KEY_BASE64, DEMO_PUBLIC_KEY_BASE64];
+
+// The product this app's licenses are issued for (the server's `PRODUCT_ID`).
+// The same server and key can issue licenses for other apps too.
+const EXPECTED_PRODUCT_ID: &str = "localendar-mvp";
+
+// Product ids this build accepts licenses for. A build shared across a
+// product family lists each of them; empty accepts any product.
+const ACCEPTED_PRODUCT_IDS: &[&str] = &[EXPECTED_PRODUCT_ID];
+
+// Signs demo and QA tokens. Only debug builds have it, and only they trust
+// its public half, so a demo token never activates a release build. Release
+// builds still know the public half to explain why such a token fails.
+#[cfg(debug_assertions)]
+const DEMO_SIGNING_SEED: &[u8; 32] = b"localendar-debug-only-demo-key!!";
+const DEMO_PUBLIC_KEY_BASE64: &str = "80mvE4OaCnNhvgBh63xbFCHLa0tZCV8PMdlXRT8AgpY=";
+
+// Salt for `anonymous_id`, so an id can't be matched against a plain hash of
+// the email
+const ANONYMOUS_ID_SALT: &str = "localendar-anonymous-license-id:";
+
+// Prefix of the message the server signs for `server_time`, so a signed
+// timestamp can never be mistaken for a signed license
+const SERVER_TIME_CONTEXT: &str = "localendar-server-time:";
+
+/// Prefix the server signs offline activation responses under
+const OFFLINE_ACTIVATION_CONTEXT: &str = "localendar-offline-activation:";
+
+// Prefix of the message an outgoing server key signs to vouch for the key
+// replacing it (see `PinnedKey`)
+const KEY_ENDORSEMENT_CONTEXT: &str = "localendar-key-endorsement:";
+
+// How long a lapsed license keeps working when its token doesn't set
+// `grace_days`
+const GRACE_PERIOD_DAYS: i64 = 14;
+
+// A valid license this close to expiry is shown as a warning
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+// How long the app goes between online re-verifications, and how often it
+// checks instead once the license is within `EXPIRY_WARNING_DAYS` of expiry
+const OFFLINE_WINDOW_DAYS: i64 = 7;
+const NEAR_EXPIRY_RECHECK_DAYS: i64 = 1;
+
+// Plans in ascending order of entitlement. Each plan also grants every
+// feature of the plans listed before it.
+const PLAN_FEATURES: &[(&str, &[&str])] = &[
+    ("free", &["view"]),
+    ("pro", &["print", "export", "ics_import", "holidays"]),
+    ("team", &["shared_calendars", "team_admin"]),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct LicensePayload {
     pub email: String,
     pub product_id: String,
     pub plan: String,
     pub issued_at: String,
     pub expires_at: Option<String>,
+    /// Sales channel that issued the license; absent on older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Grace window after expiry, from the server's plan policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grace_days: Option<i64>,
+    /// Once expired, act as the free plan instead of becoming invalid
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub downgrade_on_expiry: bool,
+    /// Evaluation licenses are valid for anyone with an email at this domain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// Highest app version covered, e.g. "1" for any 1.x; absent means all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_cap: Option<String>,
+    /// Id of the signing key (see `key_id`); absent on older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// Machines a team license covers; absent means a single seat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seats: Option<u32>,
+    /// Terms version in force at purchase; absent on older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terms_version: Option<String>,
+    /// Device fingerprint the license is locked to; absent means any device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine_id: Option<String>,
+    /// Any claims this build doesn't know about yet
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -21,114 +132,517 @@ pub struct LicenseStatus {
     pub valid: bool,
     pub payload: Option<LicensePayload>,
     pub expires_at: Option<String>,
+    /// Floored whole days until `expires_at`: 0 when it's later today,
+    /// negative once expired (e.g. in the grace period). `None` for perpetual
+    /// licenses and expiry dates that don't parse.
+    #[serde(default)]
+    pub days_remaining: Option<i64>,
+    /// Users the license covers, from the payload's `seats`. Licenses without
+    /// a seat count, and tokens that didn't decode, count as a single seat.
+    #[serde(default = "default_seats")]
+    pub seats: u32,
     pub grace_period: bool,
-    pub error: Option<String>,
+    /// Expired, but the token asked to fall back to the free plan rather than
+    /// lock the user out. `valid` stays true and features are those of "free".
+    #[serde(default)]
+    pub downgraded: bool,
+    /// Index into the configured public keys of the key whose signature
+    /// checked out; `None` if no key did. A token from an older key can be
+    /// refreshed before that key is retired.
+    #[serde(default)]
+    pub matched_key_index: Option<usize>,
+    pub error: Option<LicenseError>,
 }
 
-/// Verify an offline license token
-/// Token format: base64(json_payload) + "." + base64(signature)
-pub fn verify_license_token(token: &str) -> LicenseStatus {
-    // Split token into payload and signature
-    let parts: Vec<&str> = token.split('.').collect();
-    if parts.len() != 2 {
-        return LicenseStatus {
+impl LicenseStatus {
+    /// A token turned away before its payload could be trusted
+    pub fn rejected(error: LicenseError) -> Self {
+        LicenseStatus {
             valid: false,
             payload: None,
             expires_at: None,
+            days_remaining: None,
+            seats: 1,
             grace_period: false,
-            error: Some("Invalid token format".to_string()),
+            downgraded: false,
+            matched_key_index: None,
+            error: Some(error),
+        }
+    }
+
+    /// Why the license isn't valid, as shown to the user
+    pub fn error_message(&self) -> String {
+        self.error
+            .as_ref()
+            .map_or_else(|| "Invalid license".to_string(), |e| e.to_string())
+    }
+}
+
+/// Why a license isn't valid. Serialized as its message, so the frontend can
+/// show it as-is and metadata cached by older builds still loads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseError {
+    InvalidFormat,
+    DecodeFailed,
+    InvalidPayload,
+    InvalidPublicKey,
+    UnknownKey,
+    BadSignature,
+    DemoToken,
+    Expired,
+    Revoked,
+    /// The product the license is for
+    WrongProduct(String),
+    WrongDomain,
+    WrongDevice,
+    /// The license's seat count
+    SeatsExhausted(u32),
+    /// The plan that was asked for
+    UnknownPlan(String),
+    PlanTooLow(String),
+    ServerMismatch,
+    RejectedByServer,
+    /// Anything else, e.g. a server message this build doesn't know
+    Other(String),
+}
+
+impl std::fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LicenseError::InvalidFormat => write!(f, "Invalid token format"),
+            LicenseError::DecodeFailed => write!(f, "Failed to decode token"),
+            LicenseError::InvalidPayload => write!(f, "Failed to parse payload"),
+            LicenseError::InvalidPublicKey => write!(f, "Invalid public key"),
+            LicenseError::UnknownKey => write!(f, "License signed by an unknown key"),
+            LicenseError::BadSignature => write!(f, "Signature verification failed"),
+            LicenseError::DemoToken => write!(f, "Debug demo token not valid in release build"),
+            LicenseError::Expired => write!(f, "License has expired"),
+            LicenseError::Revoked => write!(f, "License revoked"),
+            LicenseError::WrongProduct(product_id) => {
+                write!(f, "Wrong product: this license is for '{}'", product_id)
+            }
+            LicenseError::WrongDomain => write!(f, "License is for a different email domain"),
+            LicenseError::WrongDevice => write!(f, "License bound to a different device"),
+            LicenseError::SeatsExhausted(seats) => write!(f, "All {} seats are in use", seats),
+            LicenseError::UnknownPlan(plan) => write!(f, "Unknown plan '{}'", plan),
+            LicenseError::PlanTooLow(plan) => {
+                write!(f, "Plan too low: requires {} or higher", plan)
+            }
+            LicenseError::ServerMismatch => {
+                write!(f, "License server response does not match license")
+            }
+            LicenseError::RejectedByServer => write!(f, "License rejected by server"),
+            LicenseError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl LicenseError {
+    /// The error a message stands for; the inverse of `Display`. Messages
+    /// from older builds or other servers become `Other`.
+    fn from_message(message: &str) -> Self {
+        let unit = [
+            LicenseError::InvalidFormat,
+            LicenseError::DecodeFailed,
+            LicenseError::InvalidPayload,
+            LicenseError::InvalidPublicKey,
+            LicenseError::UnknownKey,
+            LicenseError::BadSignature,
+            LicenseError::DemoToken,
+            LicenseError::Expired,
+            LicenseError::Revoked,
+            LicenseError::WrongDomain,
+            LicenseError::WrongDevice,
+            LicenseError::ServerMismatch,
+            LicenseError::RejectedByServer,
+        ];
+        if let Some(error) = unit.into_iter().find(|error| error.to_string() == message) {
+            return error;
+        }
+
+        let quoted = |prefix: &str| {
+            message
+                .strip_prefix(prefix)?
+                .strip_prefix('\'')?
+                .strip_suffix('\'')
+                .map(str::to_string)
+        };
+        if let Some(product_id) = quoted("Wrong product: this license is for ") {
+            return LicenseError::WrongProduct(product_id);
+        }
+        if let Some(plan) = quoted("Unknown plan ") {
+            return LicenseError::UnknownPlan(plan);
+        }
+        if let Some(plan) = message
+            .strip_prefix("Plan too low: requires ")
+            .and_then(|rest| rest.strip_suffix(" or higher"))
+        {
+            return LicenseError::PlanTooLow(plan.to_string());
+        }
+        if let Some(seats) = message
+            .strip_prefix("All ")
+            .and_then(|rest| rest.strip_suffix(" seats are in use"))
+            .and_then(|seats| seats.parse().ok())
+        {
+            return LicenseError::SeatsExhausted(seats);
+        }
+        LicenseError::Other(message.to_string())
+    }
+}
+
+impl Serialize for LicenseError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LicenseError {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|message| LicenseError::from_message(&message))
+    }
+}
+
+/// Strip common paste artifacts: surrounding whitespace, a leading
+/// `Bearer ` (any case) from Authorization headers, and the quotes left
+/// over from copying a JSON string value.
+pub fn normalize_token(token: &str) -> &str {
+    let mut token = token.trim();
+    loop {
+        let before = token;
+        if let Some(prefix) = token.get(..7) {
+            if prefix.eq_ignore_ascii_case("bearer ") {
+                token = token[7..].trim();
+            }
+        }
+        for quote in ['"', '\''] {
+            if token.len() >= 2 && token.starts_with(quote) && token.ends_with(quote) {
+                token = token[1..token.len() - 1].trim();
+            }
+        }
+        if token == before {
+            return token;
+        }
+    }
+}
+
+/// What the signature in a token covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenVersion {
+    /// `base64(json).base64(sig)`, signed over the base64 payload string
+    V1,
+    /// `v2.base64(json).base64(sig)`, signed over the raw payload JSON bytes,
+    /// the scheme most third-party issuers use
+    V2,
+}
+
+/// Split a token into its version, base64 payload and base64 signature
+pub fn split_token(token: &str) -> Option<(TokenVersion, &str, &str)> {
+    let parts: Vec<&str> = token.split('.').collect();
+    match parts.as_slice() {
+        [payload, signature] => Some((TokenVersion::V1, payload, signature)),
+        ["v2", payload, signature] => Some((TokenVersion::V2, payload, signature)),
+        _ => None,
+    }
+}
+
+/// Decode base64 in any of the common variants (standard or URL-safe, with
+/// or without padding)
+fn decode_lenient(input: &str) -> Option<Vec<u8>> {
+    [
+        general_purpose::STANDARD,
+        general_purpose::STANDARD_NO_PAD,
+        general_purpose::URL_SAFE,
+        general_purpose::URL_SAFE_NO_PAD,
+    ]
+    .iter()
+    .find_map(|engine| engine.decode(input).ok())
+}
+
+/// The canonical form of a pasted token: normalized as in `normalize_token`,
+/// with any whitespace inside it (e.g. from line-wrapped emails) removed and
+/// both segments re-encoded in standard, padded base64. Errors if the token
+/// doesn't parse, with the error `verify_license_token` gives for it; the
+/// signature is not checked.
+pub fn canonicalize_token(token: &str) -> Result<String, LicenseError> {
+    let token: String = normalize_token(token)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let (version, payload_b64, signature_b64) =
+        split_token(&token).ok_or(LicenseError::InvalidFormat)?;
+
+    let payload = decode_lenient(payload_b64).ok_or(LicenseError::DecodeFailed)?;
+    let payload_str = std::str::from_utf8(&payload).map_err(|_| LicenseError::DecodeFailed)?;
+    serde_json::from_str::<LicensePayload>(payload_str)
+        .map_err(|_| LicenseError::InvalidPayload)?;
+    let signature = decode_lenient(signature_b64).ok_or(LicenseError::DecodeFailed)?;
+    if signature.len() != SIGNATURE_LENGTH {
+        return Err(LicenseError::DecodeFailed);
+    }
+
+    let canonical = format!(
+        "{}.{}",
+        general_purpose::STANDARD.encode(&payload),
+        general_purpose::STANDARD.encode(&signature)
+    );
+    Ok(match version {
+        TokenVersion::V1 => canonical,
+        TokenVersion::V2 => format!("v2.{}", canonical),
+    })
+}
+
+const DEEP_LINK_PREFIX: &str = "localendar://license?";
+
+/// A `localendar://license?token=...` link carrying the token, for QR codes
+/// and the mobile companion app. Both segments are re-encoded as unpadded
+/// URL-safe base64, so the token needs no further escaping.
+pub fn license_deep_link(token: &str) -> Result<String, String> {
+    let canonical = canonicalize_token(token).map_err(|e| e.to_string())?;
+    let url_safe: Vec<String> = canonical
+        .split('.')
+        .map(|segment| match general_purpose::STANDARD.decode(segment) {
+            Ok(bytes) => general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+            // The "v2" version segment
+            Err(_) => segment.to_string(),
+        })
+        .collect();
+    Ok(format!("{}token={}", DEEP_LINK_PREFIX, url_safe.join(".")))
+}
+
+/// The token from a link made by `license_deep_link`, in canonical form
+pub fn token_from_deep_link(link: &str) -> Result<String, String> {
+    let query = link
+        .trim()
+        .strip_prefix(DEEP_LINK_PREFIX)
+        .ok_or("Not a LoCalendar license link")?;
+    let token = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("token="))
+        .ok_or("License link has no token")?;
+    canonicalize_token(token).map_err(|e| e.to_string())
+}
+
+/// Verify an offline license token
+/// Token format: base64(json_payload) + "." + base64(signature), optionally
+/// prefixed with a version segment (see `TokenVersion`). `fingerprint` is
+/// this device's (see `device::device_fingerprint`): a license carrying a
+/// `machine_id` only counts on that machine, unbound licenses work anywhere.
+pub fn verify_license_token(token: &str, fingerprint: &str) -> LicenseStatus {
+    verify_license_token_at(token, fingerprint, Utc::now())
+}
+
+/// `verify_license_token` as if the current time were `now`, so expiry can
+/// be checked at any instant without touching the system clock
+pub fn verify_license_token_at(
+    token: &str,
+    fingerprint: &str,
+    now: DateTime<Utc>,
+) -> LicenseStatus {
+    verify_at(token, fingerprint, PUBLIC_KEYS, ACCEPTED_PRODUCT_IDS, now)
+}
+
+/// `verify_license_token` plus how long it took, in microseconds, for the
+/// diagnostics screen
+pub fn verify_with_timing(token: &str, fingerprint: &str) -> (LicenseStatus, u128) {
+    let started = std::time::Instant::now();
+    let status = verify_license_token(token, fingerprint);
+    (status, started.elapsed().as_micros())
+}
+
+// License files bigger than this aren't tokens and aren't read
+const MAX_LICENSE_FILE_BYTES: u64 = 16 * 1024;
+
+/// Verify every license file directly inside `dir`, returning (file name,
+/// status) pairs sorted by name. Files that don't hold a token (other
+/// documents, binaries, subfolders) are skipped rather than reported.
+pub fn verify_license_folder(
+    dir: &std::path::Path,
+    fingerprint: &str,
+) -> Result<Vec<(String, LicenseStatus)>, String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+
+    let mut results = Vec::new();
+    for entry in entries.flatten() {
+        let is_small_file = entry
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.len() <= MAX_LICENSE_FILE_BYTES);
+        if !is_small_file {
+            continue;
+        }
+        let Ok(contents) = std::fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let Ok(token) = canonicalize_token(&contents) else {
+            continue;
         };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        results.push((name, verify_license_token(&token, fingerprint)));
+    }
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(results)
+}
+
+/// The `kid` claim of a token, read without verifying it
+fn token_kid(token: &str) -> Option<String> {
+    let (_, payload_b64, _) = split_token(normalize_token(token))?;
+    let payload = general_purpose::STANDARD.decode(payload_b64).ok()?;
+    serde_json::from_slice::<LicensePayload>(&payload).ok()?.kid
+}
+
+/// Verify a token against a set of trusted keys. A token naming the `kid` of
+/// one of them is checked against that key only; any other token is tried
+/// against each key in turn.
+pub fn verify_license_token_with_keys(
+    token: &str,
+    fingerprint: &str,
+    public_keys: &[&str],
+) -> LicenseStatus {
+    verify_license_token_for_products(token, fingerprint, public_keys, ACCEPTED_PRODUCT_IDS)
+}
+
+/// `verify_license_token_with_keys`, only accepting licenses issued for one of
+/// `product_ids` (or for any product when it's empty)
+pub fn verify_license_token_for_products(
+    token: &str,
+    fingerprint: &str,
+    public_keys: &[&str],
+    product_ids: &[&str],
+) -> LicenseStatus {
+    verify_at(token, fingerprint, public_keys, product_ids, Utc::now())
+}
+
+fn verify_at(
+    token: &str,
+    fingerprint: &str,
+    public_keys: &[&str],
+    product_ids: &[&str],
+    now: DateTime<Utc>,
+) -> LicenseStatus {
+    // A token naming a key we have is checked against that key alone; tokens
+    // without a `kid`, or naming one this build doesn't know, try every key
+    let mut candidates: Vec<(usize, &str)> = public_keys.iter().copied().enumerate().collect();
+    if let Some(kid) = token_kid(token) {
+        if let Some(named) = candidates
+            .iter()
+            .position(|(_, key)| key_id(key).as_deref() == Some(kid.as_str()))
+        {
+            candidates = vec![candidates[named]];
+        }
+    }
+
+    let mut status = LicenseStatus::rejected(LicenseError::UnknownKey);
+    for (index, key) in candidates {
+        status = verify_with_key_at(token, key, now);
+        // A payload is only returned once the signature has checked out
+        if status.payload.is_some() {
+            status.matched_key_index = Some(index);
+            break;
+        }
+    }
+    // Only without the demo key, as in release builds, is a demo token a mistake
+    if status.payload.is_none()
+        && !public_keys.contains(&DEMO_PUBLIC_KEY_BASE64)
+        && is_debug_demo_token(token, now)
+    {
+        status.error = Some(LicenseError::DemoToken);
+    }
+
+    let error = match &status.payload {
+        Some(payload) if status.valid && !product_in(&payload.product_id, product_ids) => {
+            LicenseError::WrongProduct(payload.product_id.clone())
+        }
+        Some(payload)
+            if status.valid
+                && payload
+                    .machine_id
+                    .as_deref()
+                    .is_some_and(|machine_id| machine_id != fingerprint) =>
+        {
+            LicenseError::WrongDevice
+        }
+        _ => return status,
+    };
+    LicenseStatus {
+        valid: false,
+        grace_period: false,
+        downgraded: false,
+        error: Some(error),
+        ..status
     }
+}
+
+/// Whether `token` came from a debug build's demo generator: signed with the
+/// demo key, or carrying the all-zero placeholder signature older builds used
+fn is_debug_demo_token(token: &str, now: DateTime<Utc>) -> bool {
+    let zero_signature = split_token(normalize_token(token))
+        .and_then(|(_, _, signature_b64)| decode_lenient(signature_b64))
+        .is_some_and(|signature| {
+            signature.len() == SIGNATURE_LENGTH && signature.iter().all(|&b| b == 0)
+        });
+    zero_signature
+        || verify_with_key_at(token, DEMO_PUBLIC_KEY_BASE64, now)
+            .payload
+            .is_some()
+}
+
+fn product_in(product_id: &str, product_ids: &[&str]) -> bool {
+    product_ids.is_empty() || product_ids.contains(&product_id)
+}
+
+/// Whether this build accepts licenses issued for `product_id`
+pub fn product_accepted(product_id: &str) -> bool {
+    product_in(product_id, ACCEPTED_PRODUCT_IDS)
+}
+
+/// Verify an offline license token against a specific base64 public key
+pub fn verify_license_token_with_key(token: &str, public_key_base64: &str) -> LicenseStatus {
+    verify_with_key_at(token, public_key_base64, Utc::now())
+}
+
+fn verify_with_key_at(token: &str, public_key_base64: &str, now: DateTime<Utc>) -> LicenseStatus {
+    let token = normalize_token(token);
 
-    let payload_b64 = parts[0];
-    let signature_b64 = parts[1];
+    // Split token into payload and signature
+    let (version, payload_b64, signature_b64) = match split_token(token) {
+        Some(parts) => parts,
+        None => return LicenseStatus::rejected(LicenseError::InvalidFormat),
+    };
 
     // Decode payload
     let payload_bytes = match general_purpose::STANDARD.decode(payload_b64) {
         Ok(bytes) => bytes,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Failed to decode payload".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::DecodeFailed),
     };
 
     let payload_str = match String::from_utf8(payload_bytes) {
         Ok(s) => s,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Invalid payload encoding".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::DecodeFailed),
     };
 
     let payload: LicensePayload = match serde_json::from_str(&payload_str) {
         Ok(p) => p,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Failed to parse payload".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::InvalidPayload),
     };
 
     // Decode signature
     let signature_bytes = match general_purpose::STANDARD.decode(signature_b64) {
         Ok(bytes) => bytes,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Failed to decode signature".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::DecodeFailed),
     };
 
     if signature_bytes.len() != SIGNATURE_LENGTH {
-        return LicenseStatus {
-            valid: false,
-            payload: None,
-            expires_at: None,
-            grace_period: false,
-            error: Some("Invalid signature length".to_string()),
-        };
+        return LicenseStatus::rejected(LicenseError::DecodeFailed);
     }
 
     // Decode public key
-    let public_key_bytes = match general_purpose::STANDARD.decode(PUBLIC_KEY_BASE64) {
+    let public_key_bytes = match general_purpose::STANDARD.decode(public_key_base64) {
         Ok(bytes) => bytes,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Invalid public key".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::InvalidPublicKey),
     };
 
     if public_key_bytes.len() != PUBLIC_KEY_LENGTH {
-        return LicenseStatus {
-            valid: false,
-            payload: None,
-            expires_at: None,
-            grace_period: false,
-            error: Some("Invalid public key length".to_string()),
-        };
+        return LicenseStatus::rejected(LicenseError::InvalidPublicKey);
     }
 
     // Create verifying key
@@ -139,63 +653,74 @@ pub fn verify_license_token(token: &str) -> LicenseStatus {
             .unwrap_or(&[0u8; PUBLIC_KEY_LENGTH]),
     ) {
         Ok(key) => key,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Failed to create verifying key".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::InvalidPublicKey),
     };
 
     // Create signature
     let signature = match Signature::from_slice(&signature_bytes) {
         Ok(sig) => sig,
-        Err(_) => {
-            return LicenseStatus {
-                valid: false,
-                payload: None,
-                expires_at: None,
-                grace_period: false,
-                error: Some("Failed to parse signature".to_string()),
-            }
-        }
+        Err(_) => return LicenseStatus::rejected(LicenseError::DecodeFailed),
     };
 
     // Verify signature
-    if verifying_key
-        .verify(payload_b64.as_bytes(), &signature)
-        .is_err()
-    {
+    let message = match version {
+        TokenVersion::V1 => payload_b64.as_bytes(),
+        TokenVersion::V2 => payload_str.as_bytes(),
+    };
+    if verifying_key.verify(message, &signature).is_err() {
+        return LicenseStatus::rejected(LicenseError::BadSignature);
+    }
+
+    // Check expiry
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+        .map(|expires_at| expires_at.with_timezone(&Utc));
+    // No expiry, or one that can't be parsed, never expires
+    let is_expired = expires_at.is_some_and(|expires_at| now > expires_at);
+    let days_remaining = expires_at.map(|expires_at| days_between(now, expires_at));
+
+    if is_expired && payload.downgrade_on_expiry {
         return LicenseStatus {
-            valid: false,
-            payload: None,
-            expires_at: None,
+            valid: true,
+            payload: Some(payload.clone()),
+            expires_at: payload.expires_at.clone(),
+            days_remaining,
+            seats: seat_count(&payload),
             grace_period: false,
-            error: Some("Signature verification failed".to_string()),
+            downgraded: true,
+            matched_key_index: None,
+            error: None,
         };
     }
 
-    // Check expiry
-    let now = Utc::now();
-    let is_expired = if let Some(expires_at_str) = &payload.expires_at {
-        match DateTime::parse_from_rfc3339(expires_at_str) {
-            Ok(expires_at) => now > expires_at,
-            Err(_) => false, // If can't parse, assume not expired
-        }
-    } else {
-        false // No expiry = never expires
-    };
+    // Lapsed licenses keep working until the grace window closes
+    if is_expired && grace_ends_at(&payload).is_some_and(|ends_at| now <= ends_at) {
+        return LicenseStatus {
+            valid: true,
+            payload: Some(payload.clone()),
+            expires_at: payload.expires_at.clone(),
+            days_remaining,
+            seats: seat_count(&payload),
+            grace_period: true,
+            downgraded: false,
+            matched_key_index: None,
+            error: None,
+        };
+    }
 
     if is_expired {
         return LicenseStatus {
             valid: false,
             payload: Some(payload.clone()),
             expires_at: payload.expires_at.clone(),
+            days_remaining,
+            seats: seat_count(&payload),
             grace_period: false,
-            error: Some("License has expired".to_string()),
+            downgraded: false,
+            matched_key_index: None,
+            error: Some(LicenseError::Expired),
         };
     }
 
@@ -204,31 +729,3593 @@ pub fn verify_license_token(token: &str) -> LicenseStatus {
         valid: true,
         payload: Some(payload.clone()),
         expires_at: payload.expires_at.clone(),
+        days_remaining,
+        seats: seat_count(&payload),
         grace_period: false,
+        downgraded: false,
+        matched_key_index: None,
         error: None,
     }
 }
 
-/// Generate a demo license for testing (in production, this would be done server-side)
-#[cfg(debug_assertions)]
-pub fn generate_demo_license(email: &str) -> String {
-    use chrono::Duration;
-    
-    let payload = LicensePayload {
-        email: email.to_string(),
-        product_id: "localendar-mvp".to_string(),
-        plan: "pro".to_string(),
-        issued_at: Utc::now().to_rfc3339(),
-        expires_at: Some((Utc::now() + Duration::days(365)).to_rfc3339()),
+/// Response body of the license server's `/verify-license`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServerVerifyResponse {
+    pub valid: bool,
+    pub payload: Option<LicensePayload>,
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub expired: bool,
+    pub error: Option<LicenseError>,
+    /// The payload's seat count (1 when it has none); absent from older
+    /// servers and responses without a payload
+    #[serde(default)]
+    pub seats: Option<u32>,
+    /// The server's clock (RFC 3339) and its signature, see `verified_server_time`
+    pub server_time: Option<String>,
+    pub server_time_signature: Option<String>,
+}
+
+/// Combine an online verification result with local verification. The
+/// server's `valid` flag is never trusted on its own: the token must still
+/// verify against the bundled public key, and the payload the server
+/// describes must be the one we hold. The server can only take validity
+/// away (revocation, shortened expiry), never grant it.
+pub fn apply_server_verdict(
+    token: &str,
+    fingerprint: &str,
+    server: &ServerVerifyResponse,
+) -> LicenseStatus {
+    let local = verify_license_token(token, fingerprint);
+    if !local.valid {
+        return local;
+    }
+
+    let mismatch = match (&server.payload, &local.payload) {
+        (Some(server), Some(local)) => !payload_describes(server, local),
+        _ => false,
+    };
+    if mismatch {
+        return LicenseStatus {
+            valid: false,
+            grace_period: false,
+            error: Some(LicenseError::ServerMismatch),
+            ..local
+        };
+    }
+
+    // The server reports downgraded and grace-period licenses as plain expired
+    let lapsed = server.expired && (local.downgraded || local.grace_period);
+    if !server.valid && !lapsed {
+        let expires_at = server.expires_at.clone().or(local.expires_at);
+        return LicenseStatus {
+            valid: false,
+            grace_period: false,
+            days_remaining: expires_at.as_deref().and_then(days_until),
+            expires_at,
+            error: Some(
+                server
+                    .error
+                    .clone()
+                    .unwrap_or(LicenseError::RejectedByServer),
+            ),
+            ..local
+        };
+    }
+
+    local
+}
+
+/// Whether the server's copy of a payload matches ours. Claims the server
+/// doesn't send (it may not know newer ones, like `machine_id`) aren't
+/// compared; every claim it does send must be identical.
+fn payload_describes(server: &LicensePayload, local: &LicensePayload) -> bool {
+    let (Ok(serde_json::Value::Object(server)), Ok(serde_json::Value::Object(local))) =
+        (serde_json::to_value(server), serde_json::to_value(local))
+    else {
+        return false;
     };
+    server
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .all(|(key, value)| local.get(key) == Some(value))
+}
 
-    let payload_json = serde_json::to_string(&payload).unwrap();
-    let payload_b64 = general_purpose::STANDARD.encode(&payload_json);
-    
-    // For demo purposes, create a dummy signature
-    // In production, this would be signed with the private key
-    let dummy_signature = vec![0u8; SIGNATURE_LENGTH];
-    let signature_b64 = general_purpose::STANDARD.encode(&dummy_signature);
+fn bundled_verifying_key() -> Option<VerifyingKey> {
+    let bytes = general_purpose::STANDARD.decode(PUBLIC_KEY_BASE64).ok()?;
+    VerifyingKey::from_bytes(bytes.as_slice().try_into().ok()?).ok()
+}
 
-    format!("{}.{}", payload_b64, signature_b64)
+/// The server's current time, if it came with a valid signature from the
+/// license key. Replaying an old signed time only ever moves the clock anchor
+/// backwards, which `advance_clock_anchor` ignores.
+pub fn verified_server_time(server: &ServerVerifyResponse) -> Option<DateTime<Utc>> {
+    server_time_signed_by(server, &bundled_verifying_key()?)
+}
+
+fn server_time_signed_by(
+    server: &ServerVerifyResponse,
+    key: &VerifyingKey,
+) -> Option<DateTime<Utc>> {
+    let time = server.server_time.as_deref()?;
+    let signature_bytes = general_purpose::STANDARD
+        .decode(server.server_time_signature.as_deref()?)
+        .ok()?;
+    let signature = Signature::from_slice(&signature_bytes).ok()?;
+    let message = format!("{}{}", SERVER_TIME_CONTEXT, time);
+    key.verify(message.as_bytes(), &signature).ok()?;
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Move the clock high-water mark forward to `time`. The mark never moves
+/// backwards; an unparseable stored mark is replaced.
+pub fn advance_clock_anchor(anchor: Option<&str>, time: DateTime<Utc>) -> String {
+    let anchor = anchor
+        .and_then(|anchor| DateTime::parse_from_rfc3339(anchor).ok())
+        .map(|anchor| anchor.with_timezone(&Utc));
+    match anchor {
+        Some(anchor) if anchor >= time => anchor.to_rfc3339(),
+        _ => time.to_rfc3339(),
+    }
+}
+
+/// Exported by an air-gapped machine and carried to an admin, who runs it
+/// through the server's `/offline-activate`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfflineActivationRequest {
+    pub token: String,
+    pub machine_id: String,
+}
+
+/// The server-signed activation an air-gapped machine imports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfflineActivation {
+    pub license_id: String,
+    pub token_sha256: String,
+    pub machine_id: String,
+    pub activated_at: String,
+}
+
+/// Build the activation request code for `token` on this machine. Only a
+/// valid license can be exported.
+pub fn build_activation_request(token: &str, machine_id: &str) -> Result<String, String> {
+    let token = normalize_token(token);
+    let status = verify_license_token(token, machine_id);
+    if !status.valid {
+        return Err(status.error_message());
+    }
+    let request = OfflineActivationRequest {
+        token: token.to_string(),
+        machine_id: machine_id.to_string(),
+    };
+    let json = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    Ok(general_purpose::STANDARD.encode(json))
+}
+
+/// Check an activation response against the license it was issued for and
+/// this machine. A response signed for another token or another machine is
+/// rejected.
+pub fn verify_activation_response(
+    response: &str,
+    token: &str,
+    machine_id: &str,
+) -> Result<OfflineActivation, String> {
+    let key = bundled_verifying_key().ok_or("Invalid public key")?;
+    activation_signed_by(response, token, machine_id, &key)
+}
+
+fn activation_signed_by(
+    response: &str,
+    token: &str,
+    machine_id: &str,
+    key: &VerifyingKey,
+) -> Result<OfflineActivation, String> {
+    let (activation_b64, signature_b64) = response
+        .trim()
+        .split_once('.')
+        .ok_or("Invalid activation response format")?;
+    let signature = general_purpose::STANDARD
+        .decode(signature_b64)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or("Invalid activation response signature")?;
+    let message = format!("{}{}", OFFLINE_ACTIVATION_CONTEXT, activation_b64);
+    key.verify(message.as_bytes(), &signature)
+        .map_err(|_| "Activation response signature verification failed".to_string())?;
+
+    let activation: OfflineActivation = general_purpose::STANDARD
+        .decode(activation_b64)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or("Invalid activation response payload")?;
+    if activation.token_sha256 != sha256_hex(normalize_token(token).as_bytes()) {
+        return Err("Activation response is for a different license".to_string());
+    }
+    if activation.machine_id != machine_id {
+        return Err("Activation response is for a different machine".to_string());
+    }
+    Ok(activation)
+}
+
+fn plan_rank(plan: &str) -> Option<usize> {
+    PLAN_FEATURES.iter().position(|(name, _)| *name == plan)
+}
+
+/// Whether `plan` (or any plan below it) unlocks `feature`
+pub fn plan_grants(plan: &str, feature: &str) -> bool {
+    match plan_rank(plan) {
+        Some(rank) => PLAN_FEATURES[..=rank]
+            .iter()
+            .any(|(_, features)| features.contains(&feature)),
+        None => false,
+    }
+}
+
+/// The plan of a valid license; "free" once it has downgraded on expiry
+fn licensed_plan(status: &LicenseStatus) -> Option<&str> {
+    if status.valid && status.downgraded {
+        return Some("free");
+    }
+    status
+        .payload
+        .as_ref()
+        .filter(|_| status.valid)
+        .map(|p| p.plan.as_str())
+}
+
+/// Verify a token and additionally require its plan to rank at least
+/// `min_plan` (e.g. "pro or higher")
+pub fn verify_requires_plan(token: &str, fingerprint: &str, min_plan: &str) -> LicenseStatus {
+    let status = verify_license_token(token, fingerprint);
+    if !status.valid {
+        return status;
+    }
+
+    let error = match (
+        licensed_plan(&status).and_then(plan_rank),
+        plan_rank(min_plan),
+    ) {
+        (_, None) => LicenseError::UnknownPlan(min_plan.to_string()),
+        (Some(rank), Some(min_rank)) if rank >= min_rank => return status,
+        _ => LicenseError::PlanTooLow(min_plan.to_string()),
+    };
+    LicenseStatus {
+        valid: false,
+        grace_period: false,
+        downgraded: false,
+        matched_key_index: None,
+        error: Some(error),
+        ..status
+    }
+}
+
+/// Check several features against a token with a single verification pass.
+/// Entries may themselves be comma-separated lists ("print,export").
+pub fn features_allowed(
+    token: &str,
+    fingerprint: &str,
+    features: &[String],
+) -> HashMap<String, bool> {
+    let status = verify_license_token(token, fingerprint);
+    let plan = licensed_plan(&status);
+
+    features
+        .iter()
+        .flat_map(|entry| entry.split(','))
+        .map(str::trim)
+        .filter(|feature| !feature.is_empty())
+        .map(|feature| {
+            let granted = plan.is_some_and(|plan| plan_grants(plan, feature));
+            (feature.to_string(), granted)
+        })
+        .collect()
+}
+
+/// Why `try_use_feature` refused a feature, for the upsell prompt
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeatureDenied {
+    pub feature: String,
+    /// Lowest plan that grants the feature; `None` if no plan does
+    pub required_plan: Option<String>,
+    /// The license's plan; `None` without a valid license
+    pub current_plan: Option<String>,
+    pub reason: String,
+}
+
+/// Lowest plan in `PLAN_FEATURES` that grants `feature`
+pub fn required_plan(feature: &str) -> Option<&'static str> {
+    PLAN_FEATURES
+        .iter()
+        .find(|(_, features)| features.contains(&feature))
+        .map(|(plan, _)| *plan)
+}
+
+/// The single entitlement check feature code paths call before doing the
+/// work: `Ok` when the license grants `feature`, otherwise why not
+pub fn try_use_feature(token: &str, fingerprint: &str, feature: &str) -> Result<(), FeatureDenied> {
+    let status = verify_license_token(token, fingerprint);
+    let plan = licensed_plan(&status);
+    if plan.is_some_and(|plan| plan_grants(plan, feature)) {
+        return Ok(());
+    }
+
+    let required_plan = required_plan(feature);
+    let reason = match (plan, required_plan) {
+        (_, None) => format!("Unknown feature '{}'", feature),
+        (None, Some(_)) => status
+            .error
+            .as_ref()
+            .map_or_else(|| "No valid license".to_string(), |e| e.to_string()),
+        (Some(_), Some(required)) => format!("Requires the {} plan", required),
+    };
+    Err(FeatureDenied {
+        feature: feature.to_string(),
+        required_plan: required_plan.map(str::to_string),
+        current_plan: plan.map(str::to_string),
+        reason,
+    })
+}
+
+/// The entries of `all_features` the license doesn't grant, in order. An
+/// invalid license is missing all of them.
+pub fn missing_features(token: &str, fingerprint: &str, all_features: &[String]) -> Vec<String> {
+    let status = verify_license_token(token, fingerprint);
+    let plan = licensed_plan(&status);
+
+    all_features
+        .iter()
+        .filter(|feature| !plan.is_some_and(|plan| plan_grants(plan, feature)))
+        .cloned()
+        .collect()
+}
+
+/// Verify a token and flatten every claim (known or not) into string pairs,
+/// so generic UI can render the payload without knowing its schema
+pub fn license_claims(token: &str, fingerprint: &str) -> Result<BTreeMap<String, String>, String> {
+    let status = verify_license_token(token, fingerprint);
+    let payload = match (status.valid, &status.payload) {
+        (true, Some(payload)) => payload,
+        _ => return Err(status.error_message()),
+    };
+
+    let value =
+        serde_json::to_value(payload).map_err(|e| format!("Failed to serialize payload: {}", e))?;
+    let claims = match value {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| match value {
+                serde_json::Value::String(s) => (key, s),
+                other => (key, other.to_string()),
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+    Ok(claims)
+}
+
+/// Whether replacing the stored token with `token` would change anything the
+/// user cares about (email, plan or expiry). An invalid `token` never counts
+/// as a change, since it won't be saved; a missing or invalid stored token
+/// always does.
+pub fn license_differs(stored: Option<&str>, token: &str, fingerprint: &str) -> bool {
+    let new = verify_license_token(token, fingerprint);
+    let new = match (new.valid, new.payload) {
+        (true, Some(payload)) => payload,
+        _ => return false,
+    };
+    let old = match stored.map(|stored| verify_license_token(stored, fingerprint)) {
+        Some(LicenseStatus {
+            valid: true,
+            payload: Some(payload),
+            ..
+        }) => payload,
+        _ => return true,
+    };
+
+    let same_expiry = match (&old.expires_at, &new.expires_at) {
+        (Some(a), Some(b)) => match (
+            DateTime::parse_from_rfc3339(a),
+            DateTime::parse_from_rfc3339(b),
+        ) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        },
+        (a, b) => a == b,
+    };
+
+    old.email.trim().to_lowercase() != new.email.trim().to_lowercase()
+        || old.plan != new.plan
+        || !same_expiry
+}
+
+fn email_in_domain(email: &str, domain: &str) -> bool {
+    email
+        .trim()
+        .rsplit_once('@')
+        .is_some_and(|(_, email_domain)| email_domain.eq_ignore_ascii_case(domain.trim()))
+}
+
+/// Verify a token for a particular user. Evaluation licenses carry a
+/// `domain` and only count for emails at that domain.
+pub fn verify_license_for_user(token: &str, fingerprint: &str, user_email: &str) -> LicenseStatus {
+    let status = verify_license_token(token, fingerprint);
+    let domain = status.payload.as_ref().and_then(|p| p.domain.as_deref());
+    match domain {
+        Some(domain) if status.valid && !email_in_domain(user_email, domain) => LicenseStatus {
+            valid: false,
+            grace_period: false,
+            downgraded: false,
+            matched_key_index: None,
+            error: Some(LicenseError::WrongDomain),
+            ..status
+        },
+        _ => status,
+    }
+}
+
+/// Whether a valid token was issued to `email`, ignoring case and
+/// surrounding whitespace. Evaluation licenses match any email at their domain.
+pub fn token_matches_email(token: &str, fingerprint: &str, email: &str) -> Result<bool, String> {
+    let status = verify_license_token(token, fingerprint);
+    match status.payload.as_ref().filter(|_| status.valid) {
+        Some(LicensePayload {
+            domain: Some(domain),
+            ..
+        }) => Ok(email_in_domain(email, domain)),
+        Some(payload) => Ok(payload.email.trim().to_lowercase() == email.trim().to_lowercase()),
+        None => Err(status.error_message()),
+    }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether a valid token covers app `version`. The cap only constrains the
+/// components it names, so "1" covers every 1.x and "1.4" covers 1.4.x.
+/// A token without a cap covers every version. `None` if the token is invalid
+/// or either version can't be parsed.
+pub fn version_covered(token: &str, fingerprint: &str, version: &str) -> Option<bool> {
+    let status = verify_license_token(token, fingerprint);
+    let payload = status.payload.filter(|_| status.valid)?;
+    let Some(cap) = payload.version_cap else {
+        return Some(true);
+    };
+    let cap = parse_version(&cap)?;
+    let version = parse_version(version)?;
+    let truncated: Vec<u64> = version.iter().copied().take(cap.len()).collect();
+    Some(truncated <= cap)
+}
+
+/// Whether the user should be asked to accept `current_terms_version`: the
+/// license was issued under different terms, or before terms were recorded.
+/// `None` if the token is invalid.
+pub fn terms_acceptance_needed(
+    token: &str,
+    fingerprint: &str,
+    current_terms_version: &str,
+) -> Option<bool> {
+    let status = verify_license_token(token, fingerprint);
+    let payload = status.payload.filter(|_| status.valid)?;
+    Some(payload.terms_version.as_deref().map(str::trim) != Some(current_terms_version.trim()))
+}
+
+/// How favorable a valid license is, for comparing with `Ord`: perpetual
+/// beats any expiry, a later expiry beats an earlier one, then the higher
+/// plan wins. `None` for invalid licenses.
+fn license_rank(status: &LicenseStatus) -> Option<(bool, Option<DateTime<Utc>>, Option<usize>)> {
+    let payload = status.payload.as_ref().filter(|_| status.valid)?;
+    let expires_at = payload.expires_at.as_deref().map(|expires_at| {
+        DateTime::parse_from_rfc3339(expires_at)
+            .ok()
+            .map(|expires_at| expires_at.with_timezone(&Utc))
+    });
+    Some((
+        expires_at.is_none(),
+        expires_at.flatten(),
+        licensed_plan(status).and_then(plan_rank),
+    ))
+}
+
+/// Of two tokens, the one worth keeping. Invalid tokens lose to valid ones;
+/// on a full tie `a` is kept.
+pub fn pick_better_license(a: &str, b: &str, fingerprint: &str) -> Result<String, String> {
+    let rank_a = license_rank(&verify_license_token(a, fingerprint));
+    let rank_b = license_rank(&verify_license_token(b, fingerprint));
+    match (rank_a, rank_b) {
+        (None, None) => Err("Neither license is valid".to_string()),
+        (Some(rank_a), Some(rank_b)) if rank_b > rank_a => Ok(normalize_token(b).to_string()),
+        (None, Some(_)) => Ok(normalize_token(b).to_string()),
+        _ => Ok(normalize_token(a).to_string()),
+    }
+}
+
+/// Collapse duplicate licenses, e.g. after importing the same ones several
+/// times: of the valid tokens for each email (ignoring case), only the best
+/// (see `pick_better_license`) is kept. Invalid tokens are dropped. Kept
+/// tokens come back in the order their email first appears.
+pub fn merge_duplicate_licenses(tokens: &[String], fingerprint: &str) -> Vec<String> {
+    let mut kept: Vec<(String, String)> = Vec::new();
+    for token in tokens {
+        let status = verify_license_token(token, fingerprint);
+        let Some(payload) = status.payload.filter(|_| status.valid) else {
+            continue;
+        };
+        let email = payload.email.trim().to_lowercase();
+        match kept.iter_mut().find(|(kept_email, _)| *kept_email == email) {
+            Some((_, best)) => {
+                if let Ok(better) = pick_better_license(best, token, fingerprint) {
+                    *best = better;
+                }
+            }
+            None => kept.push((email, normalize_token(token).to_string())),
+        }
+    }
+    kept.into_iter().map(|(_, token)| token).collect()
+}
+
+/// `candidate` if it's a better license for the same customer than `stored`
+/// (see `license_rank`). The server's answer is checked locally, so it can
+/// only ever hand us a genuine, better license for the same email.
+pub fn license_update(stored: &str, candidate: &str, fingerprint: &str) -> Option<String> {
+    let same_email = match (
+        verify_license_token(stored, fingerprint).payload,
+        verify_license_token(candidate, fingerprint).payload,
+    ) {
+        (Some(old), Some(new)) => {
+            old.email.trim().to_lowercase() == new.email.trim().to_lowercase()
+        }
+        _ => false,
+    };
+    if !same_email || !license_differs(Some(stored), candidate, fingerprint) {
+        return None;
+    }
+    pick_better_license(stored, candidate, fingerprint)
+        .ok()
+        .filter(|best| best == normalize_token(candidate))
+}
+
+/// A date as shown to users, e.g. "March 5, 2025", in the local time zone
+pub fn format_local_date<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Local).format("%B %-d, %Y").to_string()
+}
+
+/// When a valid license was issued ("purchased on"), in local time. `None`
+/// if the license is invalid or its `issued_at` doesn't parse.
+pub fn license_issued_local(token: &str, fingerprint: &str) -> Option<String> {
+    let status = verify_license_token(token, fingerprint);
+    let payload = status.payload.filter(|_| status.valid)?;
+    let issued_at = DateTime::parse_from_rfc3339(&payload.issued_at).ok()?;
+    Some(format_local_date(&issued_at))
+}
+
+const SUPPORT_CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Short code to read to support over the phone, which the server's
+/// `/support-code/:code` resolves to the license: 55 bits of the SHA-256 of
+/// the token's signature in base32, then a check character, grouped as
+/// XXXX-XXXX-XXXX. `None` for invalid licenses.
+pub fn support_code(token: &str, fingerprint: &str) -> Option<String> {
+    if !verify_license_token(token, fingerprint).valid {
+        return None;
+    }
+    let (_, _, signature_b64) = split_token(normalize_token(token))?;
+    let signature = decode_lenient(signature_b64)?;
+    let digest = Sha256::digest(&signature);
+    let bits = u64::from_be_bytes(digest[..8].try_into().ok()?);
+    let data: String = (0..11)
+        .map(|i| SUPPORT_CODE_ALPHABET[((bits >> (59 - 5 * i)) & 0x1f) as usize] as char)
+        .collect();
+    let check = SUPPORT_CODE_ALPHABET[(Sha256::digest(data.as_bytes())[0] >> 3) as usize] as char;
+    let code = format!("{}{}", data, check);
+    Some(format!("{}-{}-{}", &code[..4], &code[4..8], &code[8..]))
+}
+
+/// Users `payload` covers; licenses issued before seat counts existed are
+/// single-seat
+pub fn seat_count(payload: &LicensePayload) -> u32 {
+    payload.seats.unwrap_or(1)
+}
+
+fn default_seats() -> u32 {
+    1
+}
+
+/// Seats still free on a license with `activations_used` machines activated.
+/// Licenses without a seat count are single-seat; over-activated licenses
+/// report 0 rather than a negative count.
+pub fn remaining_seats(
+    token: &str,
+    fingerprint: &str,
+    activations_used: usize,
+) -> Result<i64, String> {
+    let status = verify_license_token(token, fingerprint);
+    if !status.valid {
+        return Err(status.error_message());
+    }
+    Ok((i64::from(status.seats) - activations_used as i64).max(0))
+}
+
+/// What `verify_and_bind` has to do to use a valid license on this machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindAction {
+    /// No seat count: the license isn't tied to machines
+    Unbound,
+    AlreadyBound,
+    /// A seat is free for this machine
+    Bind,
+    SeatsExhausted,
+}
+
+/// Decide whether this machine needs one of the license's seats, given the
+/// machines already activated on it. Unlike `remaining_seats`, a license
+/// without a seat count isn't bound at all.
+pub fn bind_action(payload: &LicensePayload, activated: &[String], machine_id: &str) -> BindAction {
+    let Some(seats) = payload.seats else {
+        return BindAction::Unbound;
+    };
+    if activated.iter().any(|id| id == machine_id) {
+        BindAction::AlreadyBound
+    } else if activated.len() < seats as usize {
+        BindAction::Bind
+    } else {
+        BindAction::SeatsExhausted
+    }
+}
+
+/// `status` turned away because every seat is taken by other machines
+pub fn seats_exhausted(status: LicenseStatus) -> LicenseStatus {
+    LicenseStatus {
+        valid: false,
+        grace_period: false,
+        downgraded: false,
+        error: Some(LicenseError::SeatsExhausted(status.seats)),
+        ..status
+    }
+}
+
+/// Stable, anonymous id of a valid license for local analytics: a salted
+/// SHA-256 of its email and product, truncated to 32 hex characters. A renewed
+/// or reissued token for the same customer keeps the id. `None` for invalid
+/// licenses.
+pub fn anonymous_id(token: &str, fingerprint: &str) -> Option<String> {
+    let status = verify_license_token(token, fingerprint);
+    let payload = status.payload.filter(|_| status.valid)?;
+    Some(anonymous_id_for(&payload.email, &payload.product_id))
+}
+
+fn anonymous_id_for(email: &str, product_id: &str) -> String {
+    let email = email.trim().to_lowercase();
+    sha256_hex(format!("{}{}\0{}", ANONYMOUS_ID_SALT, email, product_id).as_bytes())[..32]
+        .to_string()
+}
+
+/// The id the server's `/revocations` list uses for a token: the SHA-256
+/// (hex) of its decoded signature
+pub fn revocation_id(token: &str) -> Option<String> {
+    let (_, _, signature_b64) = split_token(normalize_token(token))?;
+    Some(sha256_hex(&decode_lenient(signature_b64)?))
+}
+
+/// `verify_license_token`, also rejecting licenses whose `revocation_id` is
+/// in `revocations` (as fetched from the server's `/revocations`)
+pub fn verify_license_unless_revoked(
+    token: &str,
+    fingerprint: &str,
+    revocations: &HashSet<String>,
+) -> LicenseStatus {
+    let status = verify_license_token(token, fingerprint);
+    let revoked = revocation_id(token).is_some_and(|id| revocations.contains(&id));
+    if status.payload.is_some() && revoked {
+        return LicenseStatus {
+            valid: false,
+            grace_period: false,
+            downgraded: false,
+            matched_key_index: None,
+            error: Some(LicenseError::Revoked),
+            ..status
+        };
+    }
+    status
+}
+
+/// How alarming the license state is, for coloring UI
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warn,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Ok => "ok",
+            Severity::Warn => "warn",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// Invalid or expired licenses are critical; licenses in their grace period
+/// or expiring within `EXPIRY_WARNING_DAYS` warn; everything else is ok.
+pub fn license_severity(status: &LicenseStatus) -> Severity {
+    if !status.valid {
+        return Severity::Critical;
+    }
+    if status.grace_period || status.downgraded {
+        return Severity::Warn;
+    }
+
+    let expires_soon = status
+        .expires_at
+        .as_deref()
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+        .is_some_and(|expires_at| {
+            expires_at < Utc::now() + chrono::Duration::days(EXPIRY_WARNING_DAYS)
+        });
+    if expires_soon {
+        Severity::Warn
+    } else {
+        Severity::Ok
+    }
+}
+
+/// When to remind the user that a license expiring at `expires_at` is about to
+/// lapse: `EXPIRY_WARNING_DAYS` before, in line with `license_severity`.
+/// Perpetual licenses (and unparseable expiries) get no reminder.
+pub fn expiry_reminder_at(expires_at: Option<&str>) -> Option<DateTime<Utc>> {
+    let expires_at = DateTime::parse_from_rfc3339(expires_at?).ok()?;
+    Some(expires_at.with_timezone(&Utc) - chrono::Duration::days(EXPIRY_WARNING_DAYS))
+}
+
+/// When the app should next re-verify `token` online, given when it last
+/// did. Every `OFFLINE_WINDOW_DAYS` normally, daily near or past expiry, and
+/// no later than the expiry itself so a renewal is picked up. Never-verified
+/// or unverifiable tokens are due now; the result is never before `now`.
+pub fn next_reverification_due(
+    token: &str,
+    fingerprint: &str,
+    last_verified_at: Option<&str>,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let payload = verify_license_token_at(token, fingerprint, now).payload;
+    let last_verified = last_verified_at
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc));
+    let (Some(payload), Some(last_verified)) = (payload, last_verified) else {
+        return now;
+    };
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc));
+
+    let near_expiry =
+        expires_at.is_some_and(|t| t - chrono::Duration::days(EXPIRY_WARNING_DAYS) <= now);
+    let interval = if near_expiry {
+        NEAR_EXPIRY_RECHECK_DAYS
+    } else {
+        OFFLINE_WINDOW_DAYS
+    };
+    let mut due = last_verified + chrono::Duration::days(interval);
+    if let Some(expires_at) = expires_at.filter(|t| *t > last_verified) {
+        due = due.min(expires_at);
+    }
+    due.max(now)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GraceStatus {
+    pub in_grace: bool,
+    /// Only set while in grace
+    pub grace_ends_at: Option<String>,
+}
+
+/// When a lapsed license stops working: `grace_days` after expiry (the
+/// token's, or `GRACE_PERIOD_DAYS`). `None` for perpetual licenses.
+fn grace_ends_at(payload: &LicensePayload) -> Option<DateTime<Utc>> {
+    let expires_at = DateTime::parse_from_rfc3339(payload.expires_at.as_deref()?).ok()?;
+    let grace_days = payload.grace_days.unwrap_or(GRACE_PERIOD_DAYS);
+    Some(expires_at.with_timezone(&Utc) + chrono::Duration::days(grace_days))
+}
+
+/// Whether a genuine license has expired but is still inside its grace
+/// window, and when that window closes. Licenses that downgrade on expiry
+/// have no grace period.
+pub fn grace_status(token: &str, fingerprint: &str) -> GraceStatus {
+    let status = verify_license_token(token, fingerprint);
+    let grace_ends_at = status
+        .payload
+        .as_ref()
+        .filter(|_| status.grace_period)
+        .and_then(grace_ends_at);
+    GraceStatus {
+        in_grace: grace_ends_at.is_some(),
+        grace_ends_at: grace_ends_at.map(|ends_at| ends_at.to_rfc3339()),
+    }
+}
+
+/// Where the user stands, so the UI can route on launch: a fresh install
+/// gets the welcome/activate flow, a lapsed license the renew flow
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum LicensingState {
+    NeverActivated,
+    Active,
+    Expired,
+    Grace,
+    /// A genuine license, but issued for another machine or product
+    Mismatched,
+    /// The stored token doesn't verify, or the clock was rolled back
+    Tampered,
+}
+
+/// Classify the stored token (if any). `clock_high_water` is the latest
+/// trusted time seen; a local clock behind it means it was rolled back.
+pub fn licensing_state(
+    stored: Option<&str>,
+    fingerprint: &str,
+    clock_high_water: Option<&str>,
+) -> LicensingState {
+    let token = match stored {
+        Some(token) => token,
+        None => return LicensingState::NeverActivated,
+    };
+
+    let rolled_back = clock_high_water
+        .and_then(|anchor| DateTime::parse_from_rfc3339(anchor).ok())
+        .is_some_and(|anchor| Utc::now() < anchor);
+    if rolled_back {
+        return LicensingState::Tampered;
+    }
+
+    let status = verify_license_token(token, fingerprint);
+    if status.payload.is_none() {
+        // Only a token that fails its signature or doesn't parse lands here
+        return LicensingState::Tampered;
+    }
+    match status.error {
+        _ if status.grace_period => LicensingState::Grace,
+        // A downgraded license is still valid, read-only after its expiry
+        None if status.downgraded => LicensingState::Expired,
+        None if status.valid => LicensingState::Active,
+        Some(LicenseError::Expired) => LicensingState::Expired,
+        Some(LicenseError::WrongDevice | LicenseError::WrongProduct(_)) => {
+            LicensingState::Mismatched
+        }
+        _ => LicensingState::Tampered,
+    }
+}
+
+/// What the user may do with their data, the single switch for gating edits
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLevel {
+    Full,
+    /// View but not edit: a license that downgrades on expiry has expired
+    ReadOnly,
+    Locked,
+}
+
+/// Access for the stored token (if any); arguments as for `licensing_state`.
+/// A license in its grace period keeps full access. Once expired, licenses
+/// issued with `downgrade_on_expiry` drop to read-only and others lock.
+pub fn access_level(
+    stored: Option<&str>,
+    fingerprint: &str,
+    clock_high_water: Option<&str>,
+) -> AccessLevel {
+    let state = licensing_state(stored, fingerprint, clock_high_water);
+    let downgraded =
+        stored.is_some_and(|token| verify_license_token(token, fingerprint).downgraded);
+    match state {
+        LicensingState::Active => AccessLevel::Full,
+        LicensingState::NeverActivated | LicensingState::Mismatched | LicensingState::Tampered => {
+            AccessLevel::Locked
+        }
+        LicensingState::Grace | LicensingState::Expired if downgraded => AccessLevel::ReadOnly,
+        LicensingState::Grace => AccessLevel::Full,
+        LicensingState::Expired => AccessLevel::Locked,
+    }
+}
+
+/// Everything the license screen shows, worked out in one place
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LicenseViewModel {
+    /// "Active", "Expiring soon", "Free plan", "Grace period", "Expired" or "Invalid"
+    pub status_label: String,
+    /// The plan in effect, e.g. "Pro"; `None` without a valid license
+    pub plan_label: Option<String>,
+    /// "Never expires", "Expires March 3, 2027" or "Expired March 3, 2026"
+    pub expiry_display: Option<String>,
+    pub severity: Severity,
+    /// Trial and domain evaluation licenses
+    pub is_trial: bool,
+    pub in_grace: bool,
+    /// Whole days until expiry, negative once past it; `None` for perpetual
+    pub days_remaining: Option<i64>,
+    /// The license doesn't cover this version of the app
+    pub needs_update: bool,
+}
+
+/// Floored whole days from now until `expires_at`
+fn days_until(expires_at: &str) -> Option<i64> {
+    let expires_at = DateTime::parse_from_rfc3339(expires_at).ok()?;
+    Some(days_between(Utc::now(), expires_at.with_timezone(&Utc)))
+}
+
+/// Floored whole days from `now` until `then`; negative once it's passed
+fn days_between(now: DateTime<Utc>, then: DateTime<Utc>) -> i64 {
+    (then - now).num_seconds().div_euclid(86_400)
+}
+
+/// Verify a token and describe it for display. `app_version` is the running
+/// app's version, checked against the license's `version_cap`.
+pub fn license_view_model(token: &str, fingerprint: &str, app_version: &str) -> LicenseViewModel {
+    let status = verify_license_token(token, fingerprint);
+    let in_grace = status.grace_period;
+    // Expired licenses still describe what they were
+    let payload = status.payload.as_ref();
+    let expires_at = payload.and_then(|p| p.expires_at.as_deref());
+    let days_remaining = expires_at.and_then(days_until);
+    let severity = license_severity(&status);
+
+    let status_label = if in_grace {
+        "Grace period"
+    } else if status.valid && status.downgraded {
+        "Free plan"
+    } else if status.valid && severity == Severity::Warn {
+        "Expiring soon"
+    } else if status.valid {
+        "Active"
+    } else if days_remaining.is_some_and(|days| days < 0) {
+        "Expired"
+    } else {
+        "Invalid"
+    };
+    let plan_label = licensed_plan(&status).map(|plan| {
+        let mut chars = plan.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    });
+    let expiry_display = payload.map(|p| match p.expires_at.as_deref() {
+        None => "Never expires".to_string(),
+        Some(at) => match DateTime::parse_from_rfc3339(at) {
+            Ok(at) if at < Utc::now() => format!("Expired {}", format_local_date(&at)),
+            Ok(at) => format!("Expires {}", format_local_date(&at)),
+            Err(_) => format!("Expires {}", at),
+        },
+    });
+
+    LicenseViewModel {
+        status_label: status_label.to_string(),
+        plan_label,
+        expiry_display,
+        severity,
+        is_trial: matches!(
+            payload.and_then(|p| p.source.as_deref()),
+            Some("trial" | "eval")
+        ),
+        in_grace,
+        days_remaining,
+        needs_update: version_covered(token, fingerprint, app_version) == Some(false),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+/// One line of the "diagnose my license" checklist
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CheckResult {
+    pub check: String,
+    pub outcome: CheckOutcome,
+    pub message: String,
+}
+
+fn check(check: &str, outcome: CheckOutcome, message: impl Into<String>) -> CheckResult {
+    CheckResult {
+        check: check.to_string(),
+        outcome,
+        message: message.into(),
+    }
+}
+
+/// Whether this build can verify `status`'s token: a debug demo token is
+/// rejected by release builds, and a debug build without a release public key
+/// (`offline_available` false) only verifies demo tokens
+fn environment_check(status: &LicenseStatus, offline_available: bool) -> CheckResult {
+    if status.error == Some(LicenseError::DemoToken) {
+        check(
+            "env",
+            CheckOutcome::Fail,
+            "A debug demo token; this build only accepts licenses from the license server",
+        )
+    } else if offline_available {
+        check(
+            "env",
+            CheckOutcome::Pass,
+            "This build has a release public key",
+        )
+    } else {
+        check(
+            "env",
+            CheckOutcome::Skip,
+            "Debug build without LOCALENDAR_PUBLIC_KEY; only demo tokens verify",
+        )
+    }
+}
+
+/// Run every check a license goes through and report each one, so support can
+/// see exactly which one rejects it. `server` is the last verdict from the
+/// server for this token and `device_activated` whether this device is known
+/// to be activated; either is skipped when unknown. Once the format or
+/// signature check fails the rest are skipped, as the claims can't be trusted.
+pub fn diagnose_license(
+    token: &str,
+    fingerprint: &str,
+    server: Option<&LicenseStatus>,
+    device_activated: Option<bool>,
+) -> Vec<CheckResult> {
+    use CheckOutcome::{Fail, Pass, Skip};
+    const CHECKS: [&str; 8] = [
+        "format",
+        "env",
+        "signature",
+        "product",
+        "expiry",
+        "grace",
+        "revocation",
+        "device",
+    ];
+    let skip_rest = |mut results: Vec<CheckResult>, reason: &str| {
+        for name in &CHECKS[results.len()..] {
+            results.push(check(name, Skip, reason));
+        }
+        results
+    };
+
+    let mut results = Vec::new();
+    let parsed = split_token(normalize_token(token))
+        .and_then(|(_, payload_b64, _)| general_purpose::STANDARD.decode(payload_b64).ok())
+        .and_then(|bytes| serde_json::from_slice::<LicensePayload>(&bytes).ok());
+    if parsed.is_none() {
+        results.push(check(
+            "format",
+            Fail,
+            "Not a license token, or its payload doesn't parse",
+        ));
+        return skip_rest(results, "Skipped: the token format is invalid");
+    }
+    results.push(check("format", Pass, "Token parses"));
+
+    let status = verify_license_token(token, fingerprint);
+    results.push(environment_check(&status, offline_verification_available()));
+    let payload = match status.payload.clone() {
+        Some(payload) => payload,
+        None => {
+            let error = status.error.map_or_else(
+                || "Signature verification failed".to_string(),
+                |e| e.to_string(),
+            );
+            results.push(check("signature", Fail, error));
+            return skip_rest(results, "Skipped: the signature is invalid");
+        }
+    };
+    results.push(check("signature", Pass, "Signed by a trusted key"));
+
+    results.push(if ACCEPTED_PRODUCT_IDS.is_empty() {
+        check(
+            "product",
+            Skip,
+            format!(
+                "Issued for '{}'; this build accepts any product",
+                payload.product_id
+            ),
+        )
+    } else if product_accepted(&payload.product_id) {
+        check(
+            "product",
+            Pass,
+            format!("Issued for '{}'", payload.product_id),
+        )
+    } else {
+        check(
+            "product",
+            Fail,
+            format!(
+                "Issued for '{}'; this build accepts {}",
+                payload.product_id,
+                ACCEPTED_PRODUCT_IDS.join(", ")
+            ),
+        )
+    });
+
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok());
+    let expired = expires_at.is_some_and(|expires_at| Utc::now() > expires_at);
+    results.push(match (&payload.expires_at, expired) {
+        (None, _) => check("expiry", Pass, "Perpetual license"),
+        (Some(at), false) => check("expiry", Pass, format!("Expires at {}", at)),
+        (Some(at), true) if status.downgraded => check(
+            "expiry",
+            Fail,
+            format!("Expired at {}; running as the free plan", at),
+        ),
+        (Some(at), true) => check("expiry", Fail, format!("Expired at {}", at)),
+    });
+
+    let grace = grace_status(token, fingerprint);
+    results.push(if !expired {
+        check("grace", Skip, "Not expired")
+    } else if status.downgraded {
+        check("grace", Skip, "Downgrades to the free plan instead")
+    } else if let Some(ends_at) = grace.grace_ends_at {
+        check(
+            "grace",
+            Pass,
+            format!("In the grace period until {}", ends_at),
+        )
+    } else {
+        check("grace", Fail, "The grace period has ended")
+    });
+
+    results.push(match server {
+        None => check("revocation", Skip, "Not checked with the server yet"),
+        Some(server) if !server.valid => check(
+            "revocation",
+            Fail,
+            server
+                .error
+                .as_ref()
+                .map_or_else(|| "Rejected by the server".to_string(), |e| e.to_string()),
+        ),
+        Some(_) => check("revocation", Pass, "Confirmed by the server"),
+    });
+
+    let bound_elsewhere = payload
+        .machine_id
+        .as_deref()
+        .is_some_and(|machine_id| machine_id != fingerprint);
+    results.push(match device_activated {
+        _ if bound_elsewhere => check("device", Fail, "The license is bound to another device"),
+        None => check("device", Skip, "Device activation unknown"),
+        Some(true) => check("device", Pass, "This device is activated"),
+        Some(false) => check(
+            "device",
+            Fail,
+            "This device is not activated for the license",
+        ),
+    });
+
+    results
+}
+
+/// Anonymized snapshot of the licensing state, safe to attach to support tickets.
+/// Never contains the token or the raw email.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Diagnostics {
+    pub app_version: String,
+    pub key_fingerprint: String,
+    pub license_present: bool,
+    pub email_hash: Option<String>,
+    pub plan: Option<String>,
+    pub product_id: Option<String>,
+    pub expires_at: Option<String>,
+    pub valid: Option<bool>,
+    pub error: Option<LicenseError>,
+    pub last_verified_at: Option<String>,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether `key` is a base64 Ed25519 public key
+pub fn is_valid_public_key(key: &str) -> bool {
+    general_purpose::STANDARD
+        .decode(key)
+        .ok()
+        .and_then(|bytes| <[u8; PUBLIC_KEY_LENGTH]>::try_from(bytes).ok())
+        .is_some_and(|bytes| VerifyingKey::from_bytes(&bytes).is_ok())
+}
+
+/// A server public key pinned from `/pubkey`, with the endorsement that got
+/// it accepted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinnedKey {
+    pub public_key: String,
+    /// Base64 signature by a bundled key over `KEY_ENDORSEMENT_CONTEXT` +
+    /// `public_key`. Not needed for a key that is bundled itself.
+    #[serde(default)]
+    pub endorsement: Option<String>,
+}
+
+impl PinnedKey {
+    /// Whether this build may trust the key: it is bundled, or a bundled key
+    /// endorsed it
+    pub fn is_trusted(&self) -> bool {
+        key_is_trusted(self, PUBLIC_KEYS)
+    }
+}
+
+fn key_is_trusted(pin: &PinnedKey, bundled_keys: &[&str]) -> bool {
+    if !is_valid_public_key(&pin.public_key) {
+        return false;
+    }
+    if bundled_keys.contains(&pin.public_key.as_str()) {
+        return true;
+    }
+    let Some(signature) = pin
+        .endorsement
+        .as_deref()
+        .and_then(|endorsement| general_purpose::STANDARD.decode(endorsement.trim()).ok())
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+    else {
+        return false;
+    };
+    let message = format!("{}{}", KEY_ENDORSEMENT_CONTEXT, pin.public_key);
+    bundled_keys
+        .iter()
+        .filter_map(|key| general_purpose::STANDARD.decode(key).ok())
+        .filter_map(|bytes| <[u8; PUBLIC_KEY_LENGTH]>::try_from(bytes).ok())
+        .filter_map(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .any(|key| key.verify(message.as_bytes(), &signature).is_ok())
+}
+
+/// Decide which server public key to keep pinned after fetching `fetched`
+/// and its `endorsement`. Only a key this build trusts (see
+/// `PinnedKey::is_trusted`) is ever pinned. The first such key is pinned;
+/// after that a different key is rejected unless the user explicitly asked
+/// to re-pin, so a key swap can never happen silently.
+pub fn resolve_key_pin(
+    pinned: Option<&PinnedKey>,
+    fetched: &str,
+    endorsement: Option<&str>,
+    repin: bool,
+) -> Result<PinnedKey, String> {
+    resolve_key_pin_with(pinned, fetched, endorsement, repin, PUBLIC_KEYS)
+}
+
+fn resolve_key_pin_with(
+    pinned: Option<&PinnedKey>,
+    fetched: &str,
+    endorsement: Option<&str>,
+    repin: bool,
+    bundled_keys: &[&str],
+) -> Result<PinnedKey, String> {
+    let fetched = PinnedKey {
+        public_key: fetched.trim().to_string(),
+        endorsement: endorsement.map(|e| e.trim().to_string()),
+    };
+    if !is_valid_public_key(&fetched.public_key) {
+        return Err("Server sent an invalid public key".to_string());
+    }
+    if !key_is_trusted(&fetched, bundled_keys) {
+        return Err(
+            "Server public key is not endorsed by a key this build trusts; not pinning it"
+                .to_string(),
+        );
+    }
+
+    match pinned {
+        Some(pinned) if pinned.public_key != fetched.public_key && !repin => Err(
+            "Server public key differs from the pinned key; re-pin to accept the new key"
+                .to_string(),
+        ),
+        _ => Ok(fetched),
+    }
+}
+
+/// Verify a token against the bundled keys plus the pinned key, if this build
+/// trusts it. The pinned key never replaces the bundled ones.
+pub fn verify_license_token_pinned(
+    token: &str,
+    fingerprint: &str,
+    pinned: Option<&PinnedKey>,
+) -> LicenseStatus {
+    verify_license_token_with_keys(token, fingerprint, &pinned_keys(pinned, PUBLIC_KEYS))
+}
+
+fn pinned_keys<'a>(pinned: Option<&'a PinnedKey>, bundled_keys: &[&'a str]) -> Vec<&'a str> {
+    let mut keys = bundled_keys.to_vec();
+    if let Some(pin) = pinned.filter(|pin| key_is_trusted(pin, bundled_keys)) {
+        if !keys.contains(&pin.public_key.as_str()) {
+            keys.push(&pin.public_key);
+        }
+    }
+    keys
+}
+
+/// The base64 public key this build verifies against
+pub fn configured_public_key() -> &'static str {
+    PUBLIC_KEY_BASE64
+}
+
+/// Short id of a base64 public key: the first 8 bytes of its SHA-256, in
+/// hex. The server stamps the same id into tokens as `kid`.
+pub fn key_id(public_key_base64: &str) -> Option<String> {
+    general_purpose::STANDARD
+        .decode(public_key_base64)
+        .ok()
+        .map(|bytes| sha256_hex(&bytes)[..16].to_string())
+}
+
+/// Short fingerprint of the public key this build verifies against
+pub fn public_key_fingerprint() -> String {
+    key_id(PUBLIC_KEY_BASE64).unwrap_or_else(|| "invalid".to_string())
+}
+
+/// Whether this build has a real public key to verify licenses offline with,
+/// rather than only the all-zero placeholder a debug build gets when
+/// `LOCALENDAR_PUBLIC_KEY` is unset. The debug demo key doesn't count.
+pub fn offline_verification_available() -> bool {
+    has_real_public_key(PUBLIC_KEYS)
+}
+
+fn has_real_public_key(public_keys: &[&str]) -> bool {
+    public_keys
+        .iter()
+        .filter(|key| **key != DEMO_PUBLIC_KEY_BASE64)
+        .any(|key| usable_public_key(key))
+}
+
+fn usable_public_key(public_key_base64: &str) -> bool {
+    general_purpose::STANDARD
+        .decode(public_key_base64)
+        .ok()
+        .and_then(|bytes| <[u8; PUBLIC_KEY_LENGTH]>::try_from(bytes).ok())
+        .is_some_and(|bytes| {
+            bytes != [0u8; PUBLIC_KEY_LENGTH] && VerifyingKey::from_bytes(&bytes).is_ok()
+        })
+}
+
+/// Build the diagnostics bundle for an (optional) stored token
+pub fn build_diagnostics(
+    token: Option<&str>,
+    fingerprint: &str,
+    last_verified_at: Option<String>,
+    app_version: &str,
+) -> Diagnostics {
+    let status = token.map(|token| verify_license_token(token, fingerprint));
+    let payload = status.as_ref().and_then(|s| s.payload.as_ref());
+
+    Diagnostics {
+        app_version: app_version.to_string(),
+        key_fingerprint: public_key_fingerprint(),
+        license_present: token.is_some(),
+        email_hash: payload.map(|p| sha256_hex(p.email.trim().to_lowercase().as_bytes())),
+        plan: payload.map(|p| p.plan.clone()),
+        product_id: payload.map(|p| p.product_id.clone()),
+        expires_at: payload.and_then(|p| p.expires_at.clone()),
+        valid: status.as_ref().map(|s| s.valid),
+        error: status.as_ref().and_then(|s| s.error.clone()),
+        last_verified_at,
+    }
+}
+
+/// Sign a payload with the debug-only demo key
+#[cfg(debug_assertions)]
+pub(crate) fn sign_demo_token(payload: &LicensePayload) -> String {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let payload_json = serde_json::to_string(payload).unwrap();
+    let payload_b64 = general_purpose::STANDARD.encode(&payload_json);
+    let signature = SigningKey::from_bytes(DEMO_SIGNING_SEED).sign(payload_b64.as_bytes());
+    let signature_b64 = general_purpose::STANDARD.encode(signature.to_bytes());
+
+    format!("{}.{}", payload_b64, signature_b64)
+}
+
+#[cfg(debug_assertions)]
+fn demo_payload(email: &str, expires_at: DateTime<Utc>) -> LicensePayload {
+    LicensePayload {
+        email: email.to_string(),
+        product_id: EXPECTED_PRODUCT_ID.to_string(),
+        plan: "pro".to_string(),
+        issued_at: (expires_at - chrono::Duration::days(365)).to_rfc3339(),
+        expires_at: Some(expires_at.to_rfc3339()),
+        source: None,
+        grace_days: None,
+        downgrade_on_expiry: false,
+        domain: None,
+        version_cap: None,
+        kid: None,
+        seats: None,
+        terms_version: None,
+        machine_id: None,
+        extra: HashMap::new(),
+    }
+}
+
+/// Generate a demo license for testing (in production, this would be done server-side)
+#[cfg(debug_assertions)]
+pub fn generate_demo_license(email: &str) -> String {
+    sign_demo_token(&demo_payload(
+        email,
+        Utc::now() + chrono::Duration::days(365),
+    ))
+}
+
+/// The token to store to put the app in a licensing state for QA: `valid`,
+/// `expired`, `grace`, `tampered`, or `none` (nothing stored)
+#[cfg(debug_assertions)]
+pub fn debug_state_token(state: &str) -> Result<Option<String>, String> {
+    use chrono::Duration;
+    
+    let email = "qa@localendar.test";
+    let now = Utc::now();
+    let token = match state {
+        "valid" => sign_demo_token(&demo_payload(email, now + Duration::days(365))),
+        "expired" => sign_demo_token(&demo_payload(
+            email,
+            now - Duration::days(GRACE_PERIOD_DAYS + 30),
+        )),
+        "grace" => sign_demo_token(&demo_payload(email, now - Duration::days(1))),
+        "tampered" => {
+            // A valid token whose payload was edited after signing
+            let token = sign_demo_token(&demo_payload(email, now + Duration::days(365)));
+            let (_, signature_b64) = token.split_once('.').unwrap();
+            let mut edited = demo_payload(email, now + Duration::days(365));
+            edited.plan = "enterprise".to_string();
+            let edited_b64 =
+                general_purpose::STANDARD.encode(serde_json::to_string(&edited).unwrap());
+            format!("{}.{}", edited_b64, signature_b64)
+        }
+        "none" => return Ok(None),
+        other => {
+            return Err(format!(
+                "Unknown state '{}': expected valid, expired, grace, tampered or none",
+                other
+            ))
+        }
+    };
+    Ok(Some(token))
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::json;
+
+    const DEVICE: &str = "device-a";
+
+    /// Sign any payload with the demo key, which debug builds trust
+    fn sign(payload: &serde_json::Value) -> String {
+        sign_with(DEMO_SIGNING_SEED, payload)
+    }
+
+    fn sign_with(seed: &[u8; 32], payload: &serde_json::Value) -> String {
+        let payload_b64 = general_purpose::STANDARD.encode(payload.to_string());
+        let signature = SigningKey::from_bytes(seed).sign(payload_b64.as_bytes());
+        format!(
+            "{}.{}",
+            payload_b64,
+            general_purpose::STANDARD.encode(signature.to_bytes())
+        )
+    }
+
+    fn days_from_now(days: i64) -> String {
+        (Utc::now() + chrono::Duration::days(days)).to_rfc3339()
+    }
+
+    fn license(plan: &str, expires_at: Option<String>) -> serde_json::Value {
+        json!({
+            "email": "user@example.com",
+            "product_id": EXPECTED_PRODUCT_ID,
+            "plan": plan,
+            "issued_at": "2025-01-15T00:00:00+00:00",
+            "expires_at": expires_at,
+        })
+    }
+
+    fn bound_to(mut payload: serde_json::Value, machine_id: &str) -> serde_json::Value {
+        payload["machine_id"] = json!(machine_id);
+        payload
+    }
+
+    fn server_response(payload: serde_json::Value) -> ServerVerifyResponse {
+        serde_json::from_value(json!({
+            "valid": true,
+            "payload": payload,
+            "expires_at": null,
+            "error": null,
+            "server_time": null,
+            "server_time_signature": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn bound_license_only_verifies_on_its_device() {
+        let token = sign(&bound_to(license("pro", None), DEVICE));
+
+        assert!(verify_license_token(&token, DEVICE).valid);
+        let elsewhere = verify_license_token(&token, "device-b");
+        assert!(!elsewhere.valid);
+        assert_eq!(elsewhere.error, Some(LicenseError::WrongDevice));
+
+        let unbound = sign(&license("pro", None));
+        assert!(verify_license_token(&unbound, "device-b").valid);
+    }
+
+    #[test]
+    fn every_entry_point_checks_the_device() {
+        let token = sign(&bound_to(license("pro", Some(days_from_now(30))), DEVICE));
+        let features = ["export".to_string()];
+
+        assert!(features_allowed(&token, DEVICE, &features)["export"]);
+        assert!(!features_allowed(&token, "device-b", &features)["export"]);
+        assert!(try_use_feature(&token, DEVICE, "export").is_ok());
+        assert!(try_use_feature(&token, "device-b", "export").is_err());
+        assert!(verify_requires_plan(&token, DEVICE, "pro").valid);
+        assert!(!verify_requires_plan(&token, "device-b", "pro").valid);
+
+        assert_eq!(
+            licensing_state(Some(&token), DEVICE, None),
+            LicensingState::Active
+        );
+        assert_eq!(
+            licensing_state(Some(&token), "device-b", None),
+            LicensingState::Mismatched
+        );
+        assert_eq!(access_level(Some(&token), DEVICE, None), AccessLevel::Full);
+        assert_eq!(
+            access_level(Some(&token), "device-b", None),
+            AccessLevel::Locked
+        );
+    }
+
+    #[test]
+    fn server_verdict_ignores_claims_the_server_does_not_send() {
+        let local = bound_to(license("pro", None), DEVICE);
+        let token = sign(&local);
+
+        // A server that doesn't know `machine_id` describes the same license
+        let status = apply_server_verdict(&token, DEVICE, &server_response(license("pro", None)));
+        assert!(status.valid, "{:?}", status.error);
+        let status = apply_server_verdict(&token, DEVICE, &server_response(local));
+        assert!(status.valid, "{:?}", status.error);
+    }
+
+    #[test]
+    fn server_verdict_rejects_a_different_payload() {
+        let token = sign(&license("pro", None));
+
+        let status = apply_server_verdict(
+            &token,
+            DEVICE,
+            &server_response(license("enterprise", None)),
+        );
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::ServerMismatch));
+
+        let status = apply_server_verdict(
+            &token,
+            DEVICE,
+            &server_response(bound_to(license("pro", None), "device-b")),
+        );
+        assert_eq!(status.error, Some(LicenseError::ServerMismatch));
+    }
+
+    #[test]
+    fn diagnostics_bundle_is_anonymized() {
+        let email = "Jane.Doe@Example.com";
+        let mut payload = license("pro", Some(days_from_now(30)));
+        payload["email"] = json!(email);
+        let token = sign(&payload);
+
+        let diagnostics = build_diagnostics(
+            Some(&token),
+            DEVICE,
+            Some("2025-01-20T00:00:00+00:00".to_string()),
+            "1.2.3",
+        );
+        assert_eq!(diagnostics.app_version, "1.2.3");
+        assert_eq!(diagnostics.key_fingerprint, public_key_fingerprint());
+        assert!(diagnostics.license_present);
+        assert_eq!(diagnostics.plan.as_deref(), Some("pro"));
+        assert_eq!(diagnostics.product_id.as_deref(), Some(EXPECTED_PRODUCT_ID));
+        assert_eq!(diagnostics.valid, Some(true));
+        assert_eq!(diagnostics.error, None);
+        assert_eq!(
+            diagnostics.email_hash,
+            Some(sha256_hex(b"jane.doe@example.com"))
+        );
+
+        let json = serde_json::to_string(&diagnostics).unwrap();
+        let (payload_b64, signature_b64) = token.split_once('.').unwrap();
+        assert!(!json.contains(payload_b64));
+        assert!(!json.contains(signature_b64));
+        assert!(!json.to_lowercase().contains("jane.doe"));
+        assert!(!json.to_lowercase().contains("example.com"));
+    }
+
+    #[test]
+    fn diagnostics_without_a_license() {
+        let diagnostics = build_diagnostics(None, DEVICE, None, "1.2.3");
+        assert!(!diagnostics.license_present);
+        assert_eq!(diagnostics.valid, None);
+        assert_eq!(diagnostics.email_hash, None);
+    }
+
+    #[test]
+    fn features_allowed_checks_a_mixed_list_in_one_pass() {
+        let token = sign(&license("pro", None));
+        let requested = [
+            "print,export".to_string(),
+            " shared_calendars ".to_string(),
+            "view,,team_admin".to_string(),
+        ];
+
+        let allowed = features_allowed(&token, DEVICE, &requested);
+        assert_eq!(allowed.len(), 5);
+        assert!(allowed["print"]);
+        assert!(allowed["export"]);
+        assert!(allowed["view"]);
+        assert!(!allowed["shared_calendars"]);
+        assert!(!allowed["team_admin"]);
+    }
+
+    #[test]
+    fn features_allowed_denies_everything_without_a_valid_license() {
+        let allowed = features_allowed("garbage", DEVICE, &["print,view".to_string()]);
+        assert_eq!(
+            allowed,
+            HashMap::from([("print".to_string(), false), ("view".to_string(), false)])
+        );
+    }
+
+    #[test]
+    fn license_claims_flattens_every_claim() {
+        let claims = license_claims(&sign(&license("pro", None)), DEVICE).unwrap();
+        for key in ["email", "product_id", "plan", "issued_at"] {
+            assert!(claims.contains_key(key), "missing {}", key);
+        }
+        assert_eq!(claims["plan"], "pro");
+        // Unset claims are left out rather than shown as "null"
+        assert!(!claims.contains_key("expires_at"));
+        assert!(!claims.contains_key("seats"));
+
+        let mut payload = license("team", None);
+        payload["seats"] = json!(5);
+        payload["future_claim"] = json!({ "nested": [1, 2] });
+        let claims = license_claims(&sign(&payload), DEVICE).unwrap();
+        assert_eq!(claims["seats"], "5");
+        assert_eq!(claims["future_claim"], r#"{"nested":[1,2]}"#);
+    }
+
+    #[test]
+    fn license_claims_rejects_an_invalid_token() {
+        assert!(license_claims("garbage", DEVICE).is_err());
+    }
+
+    #[test]
+    fn quoted_and_bearer_prefixed_tokens_verify() {
+        let token = sign(&license("pro", None));
+
+        for pasted in [
+            format!("\"{}\"", token),
+            format!("'{}'", token),
+            format!("Bearer {}", token),
+            format!("  bearer \"{}\"\n", token),
+            format!("\"Bearer {}\"", token),
+        ] {
+            assert_eq!(normalize_token(&pasted), token);
+            let status = verify_license_token(&pasted, DEVICE);
+            assert!(status.valid, "{:?}: {:?}", pasted, status.error);
+        }
+    }
+
+    #[test]
+    fn normalize_token_leaves_clean_tokens_alone() {
+        let token = sign(&license("pro", None));
+        assert_eq!(normalize_token(&token), token);
+        // A lone quote isn't a pair to strip
+        assert_eq!(normalize_token("\"abc"), "\"abc");
+    }
+
+    /// The shared vectors in `test-vectors/license-tokens.json`
+    fn token_vectors() -> serde_json::Value {
+        serde_json::from_str(include_str!("../../test-vectors/license-tokens.json")).unwrap()
+    }
+
+    #[test]
+    fn test_vectors_verify() {
+        let vectors = token_vectors();
+        let public_key = vectors["key"]["public_key"].as_str().unwrap();
+        // Inside every vector's validity window; the trial has since lapsed
+        let during = DateTime::parse_from_rfc3339("2025-01-20T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let vectors = vectors["vectors"].as_array().unwrap();
+        let names: Vec<&serde_json::Value> = vectors.iter().map(|vector| &vector["name"]).collect();
+        assert_eq!(names, ["perpetual", "dated", "trial"]);
+        for vector in vectors {
+            let token = vector["token"].as_str().unwrap();
+            let expected: LicensePayload =
+                serde_json::from_value(vector["payload"].clone()).unwrap();
+
+            let status = verify_with_key_at(token, public_key, during);
+            assert!(status.valid, "{}: {:?}", vector["name"], status.error);
+            assert_eq!(
+                status.payload.as_ref(),
+                Some(&expected),
+                "{}",
+                vector["name"]
+            );
+
+            // The public entry point agrees on the signature
+            let status = verify_license_token_with_key(token, public_key);
+            assert_eq!(status.payload, Some(expected), "{}", vector["name"]);
+            assert_ne!(status.error, Some(LicenseError::BadSignature));
+        }
+    }
+
+    #[test]
+    fn test_vectors_reject_another_key() {
+        let vectors = token_vectors();
+        for vector in vectors["vectors"].as_array().unwrap() {
+            let status = verify_license_token_with_key(
+                vector["token"].as_str().unwrap(),
+                DEMO_PUBLIC_KEY_BASE64,
+            );
+            assert!(!status.valid);
+            assert_eq!(status.error, Some(LicenseError::BadSignature));
+        }
+    }
+
+    #[test]
+    fn license_differs_ignores_identical_claims() {
+        let expires = days_from_now(30);
+        let stored = sign(&license("pro", Some(expires.clone())));
+        assert!(!license_differs(Some(&stored), &stored, DEVICE));
+
+        // A re-issued token with the same claims, email case and expiry
+        // offset notwithstanding, changes nothing
+        let mut reissued = license(
+            "pro",
+            Some(
+                DateTime::parse_from_rfc3339(&expires)
+                    .unwrap()
+                    .with_timezone(&chrono::FixedOffset::east_opt(3600).unwrap())
+                    .to_rfc3339(),
+            ),
+        );
+        reissued["email"] = json!(" User@Example.com");
+        reissued["issued_at"] = json!("2025-02-01T00:00:00+00:00");
+        assert!(!license_differs(Some(&stored), &sign(&reissued), DEVICE));
+    }
+
+    #[test]
+    fn license_differs_spots_a_changed_plan_expiry_or_email() {
+        let expires = days_from_now(30);
+        let stored = sign(&license("pro", Some(expires.clone())));
+
+        let upgraded = sign(&license("business", Some(expires.clone())));
+        assert!(license_differs(Some(&stored), &upgraded, DEVICE));
+        let renewed = sign(&license("pro", Some(days_from_now(395))));
+        assert!(license_differs(Some(&stored), &renewed, DEVICE));
+        let perpetual = sign(&license("pro", None));
+        assert!(license_differs(Some(&stored), &perpetual, DEVICE));
+        let mut transferred = license("pro", Some(expires));
+        transferred["email"] = json!("other@example.com");
+        assert!(license_differs(Some(&stored), &sign(&transferred), DEVICE));
+
+        // Nothing stored yet, or a stored token that no longer verifies
+        assert!(license_differs(None, &stored, DEVICE));
+        assert!(license_differs(Some("garbage"), &stored, DEVICE));
+        // An invalid candidate would never be saved
+        assert!(!license_differs(Some(&stored), "garbage", DEVICE));
+    }
+
+    #[test]
+    fn severity_follows_the_license_state() {
+        let severity = |payload: serde_json::Value| {
+            license_severity(&verify_license_token(&sign(&payload), DEVICE))
+        };
+
+        assert_eq!(severity(license("pro", None)), Severity::Ok);
+        assert_eq!(
+            severity(license(
+                "pro",
+                Some(days_from_now(EXPIRY_WARNING_DAYS + 30))
+            )),
+            Severity::Ok
+        );
+        assert_eq!(
+            severity(license("pro", Some(days_from_now(EXPIRY_WARNING_DAYS - 1)))),
+            Severity::Warn
+        );
+
+        let mut in_grace = license("pro", Some(days_from_now(-1)));
+        in_grace["grace_days"] = json!(7);
+        assert_eq!(severity(in_grace), Severity::Warn);
+
+        let mut downgraded = license("pro", Some(days_from_now(-30)));
+        downgraded["downgrade_on_expiry"] = json!(true);
+        assert_eq!(severity(downgraded), Severity::Warn);
+
+        let mut lapsed = license("pro", Some(days_from_now(-30)));
+        lapsed["grace_days"] = json!(7);
+        assert_eq!(severity(lapsed), Severity::Critical);
+        assert_eq!(
+            license_severity(&verify_license_token("garbage", DEVICE)),
+            Severity::Critical
+        );
+    }
+
+    #[test]
+    fn severity_strings_match_the_serialized_form() {
+        for severity in [Severity::Ok, Severity::Warn, Severity::Critical] {
+            assert_eq!(
+                serde_json::to_value(severity).unwrap(),
+                json!(severity.as_str())
+            );
+        }
+    }
+
+    /// A `v2.` token: the signature covers the raw payload JSON bytes
+    fn sign_raw(payload: &serde_json::Value) -> String {
+        let json = payload.to_string();
+        let signature = SigningKey::from_bytes(DEMO_SIGNING_SEED).sign(json.as_bytes());
+        format!(
+            "v2.{}.{}",
+            general_purpose::STANDARD.encode(&json),
+            general_purpose::STANDARD.encode(signature.to_bytes())
+        )
+    }
+
+    #[test]
+    fn both_signing_message_modes_verify() {
+        let payload = license("pro", None);
+
+        let v1 = sign(&payload);
+        assert_eq!(split_token(&v1).unwrap().0, TokenVersion::V1);
+        assert!(verify_license_token(&v1, DEVICE).valid);
+
+        let v2 = sign_raw(&payload);
+        assert_eq!(split_token(&v2).unwrap().0, TokenVersion::V2);
+        let status = verify_license_token(&v2, DEVICE);
+        assert!(status.valid, "{:?}", status.error);
+        assert_eq!(status.payload.unwrap().plan, "pro");
+        // Canonicalizing keeps the version
+        assert_eq!(canonicalize_token(&v2).unwrap(), v2);
+    }
+
+    #[test]
+    fn signatures_only_verify_in_their_own_mode() {
+        let payload = license("pro", None);
+
+        // A base64-signed token relabelled as v2, and the reverse
+        let relabelled = format!("v2.{}", sign(&payload));
+        let status = verify_license_token(&relabelled, DEVICE);
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::BadSignature));
+
+        let stripped = sign_raw(&payload).trim_start_matches("v2.").to_string();
+        let status = verify_license_token(&stripped, DEVICE);
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::BadSignature));
+
+        assert!(split_token("v3.abc.def").is_none());
+    }
+
+    #[test]
+    fn configured_public_key_is_the_compiled_key() {
+        let expected = option_env!("LOCALENDAR_PUBLIC_KEY").unwrap_or(PLACEHOLDER_PUBLIC_KEY);
+        assert_eq!(configured_public_key(), expected);
+        assert_eq!(PUBLIC_KEYS[0], expected);
+        assert_eq!(
+            key_id(configured_public_key()).unwrap(),
+            public_key_fingerprint()
+        );
+    }
+
+    /// A verify response carrying `time`, signed with `key`
+    fn signed_time(time: &str, key: &SigningKey) -> ServerVerifyResponse {
+        let message = format!("{}{}", SERVER_TIME_CONTEXT, time);
+        let mut response = server_response(license("pro", None));
+        response.server_time = Some(time.to_string());
+        response.server_time_signature =
+            Some(general_purpose::STANDARD.encode(key.sign(message.as_bytes()).to_bytes()));
+        response
+    }
+
+    #[test]
+    fn signed_server_time_is_checked_against_the_key() {
+        let key = SigningKey::from_bytes(DEMO_SIGNING_SEED);
+        let time = "2025-06-01T12:00:00+00:00";
+
+        let response = signed_time(time, &key);
+        assert_eq!(
+            server_time_signed_by(&response, &key.verifying_key()),
+            Some(
+                DateTime::parse_from_rfc3339(time)
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+
+        // Another key, a moved time or no signature at all
+        let other = SigningKey::from_bytes(&[9; 32]);
+        assert_eq!(
+            server_time_signed_by(&signed_time(time, &other), &key.verifying_key()),
+            None
+        );
+        let mut moved = response.clone();
+        moved.server_time = Some("2030-01-01T00:00:00+00:00".to_string());
+        assert_eq!(server_time_signed_by(&moved, &key.verifying_key()), None);
+        let mut unsigned = response;
+        unsigned.server_time_signature = None;
+        assert_eq!(server_time_signed_by(&unsigned, &key.verifying_key()), None);
+    }
+
+    #[test]
+    fn clock_anchor_only_moves_forward() {
+        let earlier = "2025-01-01T00:00:00+00:00";
+        let later = DateTime::parse_from_rfc3339("2025-06-01T00:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(advance_clock_anchor(None, later), later.to_rfc3339());
+        assert_eq!(
+            advance_clock_anchor(Some(earlier), later),
+            later.to_rfc3339()
+        );
+        assert_eq!(
+            advance_clock_anchor(Some("2026-01-01T00:00:00+00:00"), later),
+            "2026-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            advance_clock_anchor(Some("garbage"), later),
+            later.to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn pro_license_is_missing_the_team_features() {
+        let all: Vec<String> = ["view", "shared_calendars", "print", "team_admin", "export"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let pro = sign(&license("pro", None));
+        assert_eq!(
+            missing_features(&pro, DEVICE, &all),
+            vec!["shared_calendars", "team_admin"]
+        );
+
+        let team = sign(&license("team", None));
+        assert!(missing_features(&team, DEVICE, &all).is_empty());
+    }
+
+    #[test]
+    fn invalid_license_is_missing_every_feature() {
+        let all = vec!["view".to_string(), "print".to_string()];
+        assert_eq!(missing_features("garbage", DEVICE, &all), all);
+        let expired = sign(&license("team", Some(days_from_now(-60))));
+        assert_eq!(missing_features(&expired, DEVICE, &all), all);
+    }
+
+    #[test]
+    fn expiry_reminder_comes_the_warning_window_before() {
+        let remind_at = expiry_reminder_at(Some("2025-06-30T09:00:00+02:00")).unwrap();
+        assert_eq!(
+            remind_at,
+            DateTime::parse_from_rfc3339("2025-06-16T07:00:00Z").unwrap()
+        );
+        assert_eq!(EXPIRY_WARNING_DAYS, 14);
+
+        // Perpetual and unparseable expiries get no reminder
+        assert_eq!(expiry_reminder_at(None), None);
+        assert_eq!(expiry_reminder_at(Some("next tuesday")), None);
+    }
+
+    fn public_key_of(seed: &[u8; 32]) -> String {
+        general_purpose::STANDARD.encode(SigningKey::from_bytes(seed).verifying_key().to_bytes())
+    }
+
+    /// `signer_seed`'s endorsement of `public_key`, as the server's
+    /// `/rotate-key` makes it
+    fn endorse(signer_seed: &[u8; 32], public_key: &str) -> String {
+        let message = format!("{}{}", KEY_ENDORSEMENT_CONTEXT, public_key);
+        general_purpose::STANDARD.encode(
+            SigningKey::from_bytes(signer_seed)
+                .sign(message.as_bytes())
+                .to_bytes(),
+        )
+    }
+
+    fn pin(public_key: &str, endorsement: Option<String>) -> PinnedKey {
+        PinnedKey {
+            public_key: public_key.to_string(),
+            endorsement,
+        }
+    }
+
+    #[test]
+    fn first_key_seen_is_pinned() {
+        let key = public_key_of(DEMO_SIGNING_SEED);
+        assert_eq!(
+            resolve_key_pin(None, &key, None, false),
+            Ok(pin(&key, None))
+        );
+        // Seeing the same key again keeps it
+        assert_eq!(
+            resolve_key_pin(Some(&pin(&key, None)), &format!(" {}\n", key), None, false),
+            Ok(pin(&key, None))
+        );
+
+        // A key the bundled key endorsed is pinned, and tokens it signs verify
+        let rotated_seed = [9; 32];
+        let rotated = public_key_of(&rotated_seed);
+        let endorsement = endorse(DEMO_SIGNING_SEED, &rotated);
+        let pinned = resolve_key_pin(None, &rotated, Some(&endorsement), false).unwrap();
+        assert!(pinned.is_trusted());
+        let token = sign_with(&rotated_seed, &license("pro", None));
+        assert!(!verify_license_token(&token, DEVICE).valid);
+        assert!(verify_license_token_pinned(&token, DEVICE, Some(&pinned)).valid);
+        // The bundled keys still count alongside it
+        let bundled = sign(&license("pro", None));
+        assert!(verify_license_token_pinned(&bundled, DEVICE, Some(&pinned)).valid);
+    }
+
+    #[test]
+    fn a_key_no_bundled_key_endorsed_is_never_trusted() {
+        let attacker_seed = [7; 32];
+        let attacker = public_key_of(&attacker_seed);
+        let forged = sign_with(&attacker_seed, &license("pro", None));
+
+        // Not pinned without an endorsement, or with one it made itself
+        for endorsement in [None, Some(endorse(&attacker_seed, &attacker))] {
+            let refused = resolve_key_pin(None, &attacker, endorsement.as_deref(), true);
+            assert!(
+                refused.as_ref().unwrap_err().contains("not endorsed"),
+                "{:?}",
+                refused
+            );
+            // Even if it is written to the pin file some other way, tokens
+            // it signed don't verify
+            let planted = pin(&attacker, endorsement);
+            assert!(!planted.is_trusted());
+            let status = verify_license_token_pinned(&forged, DEVICE, Some(&planted));
+            assert!(!status.valid);
+            assert_eq!(status.error, Some(LicenseError::BadSignature));
+        }
+
+        // An endorsement for a different key doesn't carry over
+        let other = public_key_of(&[9; 32]);
+        let stolen = pin(&attacker, Some(endorse(DEMO_SIGNING_SEED, &other)));
+        assert!(!stolen.is_trusted());
+    }
+
+    #[test]
+    fn unexpected_key_change_needs_an_explicit_repin() {
+        let pinned = pin(&public_key_of(DEMO_SIGNING_SEED), None);
+        let rotated = public_key_of(&[9; 32]);
+        let endorsement = endorse(DEMO_SIGNING_SEED, &rotated);
+
+        let refused =
+            resolve_key_pin(Some(&pinned), &rotated, Some(&endorsement), false).unwrap_err();
+        assert!(
+            refused.contains("differs from the pinned key"),
+            "{}",
+            refused
+        );
+        assert_eq!(
+            resolve_key_pin(Some(&pinned), &rotated, Some(&endorsement), true),
+            Ok(pin(&rotated, Some(endorsement)))
+        );
+
+        // Junk is never pinned, repin or not
+        assert!(resolve_key_pin(None, "not-a-key", None, true).is_err());
+        assert!(resolve_key_pin(Some(&pinned), "AAAA", None, true).is_err());
+    }
+
+    /// `token` with both segments re-encoded as unpadded URL-safe base64
+    fn url_safe(token: &str) -> String {
+        token
+            .split('.')
+            .map(|segment| {
+                general_purpose::URL_SAFE_NO_PAD
+                    .encode(general_purpose::STANDARD.decode(segment).unwrap())
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    #[test]
+    fn messy_tokens_canonicalize_to_the_same_string() {
+        let token = sign(&license("pro", None));
+        let (payload, signature) = token.split_once('.').unwrap();
+        let wrapped = format!(
+            "{}\n{}.{}\r\n {}",
+            &payload[..20],
+            &payload[20..],
+            &signature[..30],
+            &signature[30..]
+        );
+
+        for messy in [
+            token.clone(),
+            format!("  {}\n", token),
+            format!("\"{}\"", token),
+            format!("Bearer {}", token),
+            format!("'bearer \"{}\"'", token),
+            wrapped,
+            url_safe(&token),
+            format!("Bearer {}", url_safe(&token)),
+        ] {
+            assert_eq!(canonicalize_token(&messy), Ok(token.clone()), "{:?}", messy);
+        }
+        assert!(
+            verify_license_token(&canonicalize_token(&url_safe(&token)).unwrap(), DEVICE).valid
+        );
+    }
+
+    #[test]
+    fn unparseable_tokens_do_not_canonicalize() {
+        let token = sign(&license("pro", None));
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        // The same error verification gives, so set_license and
+        // verify_license describe a bad token alike
+        for (bad, error) in [
+            (String::new(), LicenseError::InvalidFormat),
+            ("no-dot-here".to_string(), LicenseError::InvalidFormat),
+            (
+                format!("{}.{}", payload, &signature[..20]),
+                LicenseError::DecodeFailed,
+            ),
+            (
+                format!("{}.{}", general_purpose::STANDARD.encode("{}"), signature),
+                LicenseError::InvalidPayload,
+            ),
+            (format!("!!!.{}", signature), LicenseError::DecodeFailed),
+        ] {
+            assert_eq!(canonicalize_token(&bad), Err(error.clone()), "{:?}", bad);
+            assert_eq!(
+                verify_license_token(&bad, DEVICE).error,
+                Some(error),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn token_with_a_known_kid_is_checked_against_that_key_only() {
+        let demo = public_key_of(DEMO_SIGNING_SEED);
+        let other = public_key_of(&[9; 32]);
+        let keys = [other.as_str(), demo.as_str()];
+
+        let mut payload = license("pro", None);
+        payload["kid"] = json!(key_id(&demo));
+        let status = verify_license_token_with_keys(&sign(&payload), DEVICE, &keys);
+        assert!(status.valid);
+        assert_eq!(status.matched_key_index, Some(1));
+
+        // Naming the other trusted key: the demo key that did sign it is
+        // never tried
+        payload["kid"] = json!(key_id(&other));
+        let status = verify_license_token_with_keys(&sign(&payload), DEVICE, &keys);
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::BadSignature));
+    }
+
+    #[test]
+    fn tokens_without_a_known_kid_try_every_key() {
+        let demo = public_key_of(DEMO_SIGNING_SEED);
+        let other = public_key_of(&[9; 32]);
+        let keys = [other.as_str(), demo.as_str()];
+
+        let unnamed = sign(&license("pro", None));
+        let mut unknown = license("pro", None);
+        unknown["kid"] = json!("0123456789abcdef");
+        for token in [unnamed, sign(&unknown)] {
+            let status = verify_license_token_with_keys(&token, DEVICE, &keys);
+            assert!(status.valid, "{:?}", status.error);
+            assert_eq!(status.matched_key_index, Some(1));
+        }
+    }
+
+    /// `expires_at` moved `days` later
+    fn plus_days(expires_at: &str, days: i64) -> String {
+        (DateTime::parse_from_rfc3339(expires_at).unwrap() + chrono::Duration::days(days))
+            .with_timezone(&Utc)
+            .to_rfc3339()
+    }
+
+    #[test]
+    fn grace_status_reports_when_the_grace_window_closes() {
+        let expired = days_from_now(-2);
+        let mut payload = license("pro", Some(expired.clone()));
+        payload["grace_days"] = json!(7);
+        assert_eq!(
+            grace_status(&sign(&payload), DEVICE),
+            GraceStatus {
+                in_grace: true,
+                grace_ends_at: Some(plus_days(&expired, 7)),
+            }
+        );
+
+        // Without its own window a token gets the default one
+        let default = sign(&license("pro", Some(expired.clone())));
+        assert_eq!(
+            grace_status(&default, DEVICE).grace_ends_at,
+            Some(plus_days(&expired, GRACE_PERIOD_DAYS))
+        );
+    }
+
+    #[test]
+    fn licenses_outside_grace_report_none() {
+        let not_in_grace = GraceStatus {
+            in_grace: false,
+            grace_ends_at: None,
+        };
+        let valid = sign(&license("pro", Some(days_from_now(30))));
+        let perpetual = sign(&license("pro", None));
+        let lapsed = sign(&license("pro", Some(days_from_now(-GRACE_PERIOD_DAYS - 1))));
+        let mut downgrading = license("pro", Some(days_from_now(-2)));
+        downgrading["downgrade_on_expiry"] = json!(true);
+        for token in [
+            valid,
+            perpetual,
+            lapsed,
+            sign(&downgrading),
+            "garbage".to_string(),
+        ] {
+            assert_eq!(grace_status(&token, DEVICE), not_in_grace);
+        }
+    }
+
+    #[test]
+    fn expired_downgrading_license_falls_back_to_free() {
+        let mut payload = license("pro", Some(days_from_now(-60)));
+        payload["downgrade_on_expiry"] = json!(true);
+        let token = sign(&payload);
+
+        let status = verify_license_token(&token, DEVICE);
+        assert!(status.valid);
+        assert!(status.downgraded);
+        assert!(!status.grace_period);
+        assert_eq!(status.error, None);
+
+        let features = vec!["view".to_string(), "print,export".to_string()];
+        let allowed = features_allowed(&token, DEVICE, &features);
+        assert!(allowed["view"]);
+        assert!(!allowed["print"]);
+        assert!(!allowed["export"]);
+        assert!(verify_requires_plan(&token, DEVICE, "free").valid);
+        assert_eq!(
+            verify_requires_plan(&token, DEVICE, "pro").error,
+            Some(LicenseError::PlanTooLow("pro".to_string()))
+        );
+    }
+
+    #[test]
+    fn expired_license_without_downgrade_locks_out() {
+        let token = sign(&license("pro", Some(days_from_now(-60))));
+
+        let status = verify_license_token(&token, DEVICE);
+        assert!(!status.valid);
+        assert!(!status.downgraded);
+        assert_eq!(status.error, Some(LicenseError::Expired));
+
+        let allowed = features_allowed(&token, DEVICE, &["view".to_string()]);
+        assert!(!allowed["view"]);
+
+        // Before it expires, a downgrading license is the full plan
+        let mut current = license("pro", Some(days_from_now(30)));
+        current["downgrade_on_expiry"] = json!(true);
+        let status = verify_license_token(&sign(&current), DEVICE);
+        assert!(status.valid && !status.downgraded);
+        assert!(features_allowed(&sign(&current), DEVICE, &["print".to_string()])["print"]);
+    }
+
+    /// Assert `better` wins against `worse`, whichever order they come in
+    fn assert_better(better: &str, worse: &str) {
+        assert_eq!(
+            pick_better_license(better, worse, DEVICE).as_deref(),
+            Ok(better)
+        );
+        assert_eq!(
+            pick_better_license(worse, better, DEVICE).as_deref(),
+            Ok(better)
+        );
+    }
+
+    #[test]
+    fn perpetual_beats_dated_and_later_beats_earlier() {
+        let perpetual = sign(&license("pro", None));
+        let later = sign(&license("pro", Some(days_from_now(400))));
+        let earlier = sign(&license("team", Some(days_from_now(30))));
+
+        assert_better(&perpetual, &later);
+        // Expiry outranks the plan
+        assert_better(&later, &earlier);
+        assert_better(&perpetual, &earlier);
+    }
+
+    #[test]
+    fn higher_plan_breaks_an_expiry_tie() {
+        let expires = days_from_now(30);
+        let team = sign(&license("team", Some(expires.clone())));
+        let pro = sign(&license("pro", Some(expires.clone())));
+        assert_better(&team, &pro);
+
+        // A full tie keeps the first
+        let other_pro = sign(&license("pro", Some(expires)));
+        assert_eq!(
+            pick_better_license(&pro, &other_pro, DEVICE).as_deref(),
+            Ok(pro.as_str())
+        );
+    }
+
+    #[test]
+    fn invalid_tokens_lose_to_valid_ones() {
+        let valid = sign(&license("free", Some(days_from_now(1))));
+        let expired = sign(&license("team", Some(days_from_now(-60))));
+        assert_better(&valid, &expired);
+        assert_better(&valid, "garbage");
+        assert_eq!(
+            pick_better_license(&expired, "garbage", DEVICE),
+            Err("Neither license is valid".to_string())
+        );
+    }
+
+    #[test]
+    fn token_matches_email_ignoring_case_and_whitespace() {
+        let token = sign(&license("pro", None));
+        assert_eq!(
+            token_matches_email(&token, DEVICE, "user@example.com"),
+            Ok(true)
+        );
+        assert_eq!(
+            token_matches_email(&token, DEVICE, "  User@EXAMPLE.com\n"),
+            Ok(true)
+        );
+        assert_eq!(
+            token_matches_email(&token, DEVICE, "someone@example.com"),
+            Ok(false)
+        );
+        assert_eq!(
+            token_matches_email(&token, DEVICE, "user@example.org"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn token_matches_email_rejects_invalid_tokens() {
+        assert!(token_matches_email("garbage", DEVICE, "user@example.com").is_err());
+        let expired = sign(&license("pro", Some(days_from_now(-60))));
+        assert_eq!(
+            token_matches_email(&expired, DEVICE, "user@example.com"),
+            Err(LicenseError::Expired.to_string())
+        );
+    }
+
+    fn eval_license(domain: &str) -> String {
+        let mut payload = license("pro", Some(days_from_now(30)));
+        payload["email"] = json!(format!("*@{}", domain));
+        payload["domain"] = json!(domain);
+        sign(&payload)
+    }
+
+    #[test]
+    fn eval_license_covers_emails_at_its_domain() {
+        let token = eval_license("corp.example");
+        for email in ["alice@corp.example", " Bob@CORP.example "] {
+            assert!(
+                verify_license_for_user(&token, DEVICE, email).valid,
+                "{}",
+                email
+            );
+            assert_eq!(token_matches_email(&token, DEVICE, email), Ok(true));
+        }
+    }
+
+    #[test]
+    fn eval_license_rejects_other_domains() {
+        let token = eval_license("corp.example");
+        for email in [
+            "alice@other.example",
+            "alice@sub.corp.example",
+            "corp.example",
+        ] {
+            let status = verify_license_for_user(&token, DEVICE, email);
+            assert!(!status.valid, "{}", email);
+            assert_eq!(status.error, Some(LicenseError::WrongDomain));
+        }
+
+        // Ordinary licenses aren't scoped to a domain
+        let personal = sign(&license("pro", None));
+        assert!(verify_license_for_user(&personal, DEVICE, "anyone@other.example").valid);
+    }
+
+    #[test]
+    fn timed_verification_matches_plain_verification() {
+        for token in [
+            sign(&license("pro", Some(days_from_now(30)))),
+            "garbage".to_string(),
+        ] {
+            let (timed, micros) = verify_with_timing(&token, DEVICE);
+            let plain = verify_license_token(&token, DEVICE);
+            assert_eq!(timed.valid, plain.valid);
+            assert_eq!(timed.payload, plain.payload);
+            assert_eq!(timed.error, plain.error);
+            // Generous bound: a single verification, even unoptimized
+            assert!(micros < 5_000_000, "{}", micros);
+        }
+        // A real signature check takes measurable time
+        let (_, micros) = verify_with_timing(&sign(&license("pro", None)), DEVICE);
+        assert!(micros > 0);
+    }
+
+    #[test]
+    fn pro_license_passes_a_pro_requirement_but_not_team() {
+        let pro = sign(&license("pro", None));
+        assert!(verify_requires_plan(&pro, DEVICE, "free").valid);
+        assert!(verify_requires_plan(&pro, DEVICE, "pro").valid);
+
+        let status = verify_requires_plan(&pro, DEVICE, "team");
+        assert!(!status.valid);
+        assert_eq!(
+            status.error,
+            Some(LicenseError::PlanTooLow("team".to_string()))
+        );
+        // Still says which license it was
+        assert_eq!(status.payload.unwrap().plan, "pro");
+    }
+
+    #[test]
+    fn plan_requirement_errors() {
+        let pro = sign(&license("pro", None));
+        assert_eq!(
+            verify_requires_plan(&pro, DEVICE, "platinum").error,
+            Some(LicenseError::UnknownPlan("platinum".to_string()))
+        );
+        let expired = sign(&license("team", Some(days_from_now(-60))));
+        assert_eq!(
+            verify_requires_plan(&expired, DEVICE, "pro").error,
+            Some(LicenseError::Expired)
+        );
+    }
+
+    #[test]
+    fn licensing_state_covers_each_state() {
+        let state = |token: &str| licensing_state(Some(token), DEVICE, None);
+
+        assert_eq!(
+            licensing_state(None, DEVICE, None),
+            LicensingState::NeverActivated
+        );
+        assert_eq!(
+            state(&sign(&license("pro", Some(days_from_now(30))))),
+            LicensingState::Active
+        );
+        assert_eq!(
+            state(&sign(&license("pro", Some(days_from_now(-2))))),
+            LicensingState::Grace
+        );
+        assert_eq!(
+            state(&sign(&license("pro", Some(days_from_now(-60))))),
+            LicensingState::Expired
+        );
+        let mut downgraded = license("pro", Some(days_from_now(-60)));
+        downgraded["downgrade_on_expiry"] = json!(true);
+        assert_eq!(state(&sign(&downgraded)), LicensingState::Expired);
+        assert_eq!(state("garbage"), LicensingState::Tampered);
+    }
+
+    #[test]
+    fn a_license_for_another_device_or_product_is_mismatched_not_expired() {
+        let state = |token: &str| licensing_state(Some(token), DEVICE, None);
+
+        let other_device = sign(&bound_to(license("pro", None), "device-b"));
+        assert_eq!(
+            verify_license_token(&other_device, DEVICE).error,
+            Some(LicenseError::WrongDevice)
+        );
+        assert_eq!(state(&other_device), LicensingState::Mismatched);
+        assert_eq!(
+            access_level(Some(&other_device), DEVICE, None),
+            AccessLevel::Locked
+        );
+
+        let mut other_app = license("pro", None);
+        other_app["product_id"] = json!("otherapp");
+        let other_app = sign(&other_app);
+        assert_eq!(
+            verify_license_token(&other_app, DEVICE).error,
+            Some(LicenseError::WrongProduct("otherapp".to_string()))
+        );
+        assert_eq!(state(&other_app), LicensingState::Mismatched);
+        assert_eq!(
+            access_level(Some(&other_app), DEVICE, None),
+            AccessLevel::Locked
+        );
+    }
+
+    #[test]
+    fn rolled_back_clock_is_tampering() {
+        let token = sign(&license("pro", None));
+        let ahead = days_from_now(1);
+        assert_eq!(
+            licensing_state(Some(&token), DEVICE, Some(&ahead)),
+            LicensingState::Tampered
+        );
+        let behind = days_from_now(-1);
+        assert_eq!(
+            licensing_state(Some(&token), DEVICE, Some(&behind)),
+            LicensingState::Active
+        );
+        // Nothing stored is a fresh install, whatever the clock says
+        assert_eq!(
+            licensing_state(None, DEVICE, Some(&ahead)),
+            LicensingState::NeverActivated
+        );
+    }
+
+    /// Answer an activation request code the way the server's
+    /// `/offline-activate` does
+    fn sign_activation(request_code: &str, key: &SigningKey) -> String {
+        let request: OfflineActivationRequest =
+            serde_json::from_slice(&general_purpose::STANDARD.decode(request_code).unwrap())
+                .unwrap();
+        let activation = OfflineActivation {
+            license_id: "license-1".to_string(),
+            token_sha256: sha256_hex(request.token.as_bytes()),
+            machine_id: request.machine_id,
+            activated_at: "2025-06-01T00:00:00+00:00".to_string(),
+        };
+        let activation_b64 =
+            general_purpose::STANDARD.encode(serde_json::to_string(&activation).unwrap());
+        let message = format!("{}{}", OFFLINE_ACTIVATION_CONTEXT, activation_b64);
+        format!(
+            "{}.{}",
+            activation_b64,
+            general_purpose::STANDARD.encode(key.sign(message.as_bytes()).to_bytes())
+        )
+    }
+
+    #[test]
+    fn offline_activation_round_trips() {
+        let key = SigningKey::from_bytes(DEMO_SIGNING_SEED);
+        let token = sign(&license("pro", None));
+
+        let request = build_activation_request(&format!("Bearer {}", token), DEVICE).unwrap();
+        let response = sign_activation(&request, &key);
+        let activation =
+            activation_signed_by(&response, &token, DEVICE, &key.verifying_key()).unwrap();
+        assert_eq!(activation.machine_id, DEVICE);
+        assert_eq!(activation.license_id, "license-1");
+    }
+
+    #[test]
+    fn offline_activation_is_bound_to_its_token_and_machine() {
+        let key = SigningKey::from_bytes(DEMO_SIGNING_SEED);
+        let token = sign(&license("pro", None));
+        let response = sign_activation(&build_activation_request(&token, DEVICE).unwrap(), &key);
+        let verify = |response: &str, token: &str, machine_id: &str| {
+            activation_signed_by(response, token, machine_id, &key.verifying_key())
+        };
+
+        assert_eq!(
+            verify(&response, &token, "device-b").unwrap_err(),
+            "Activation response is for a different machine"
+        );
+        let other = sign(&license("team", None));
+        assert_eq!(
+            verify(&response, &other, DEVICE).unwrap_err(),
+            "Activation response is for a different license"
+        );
+
+        // Another key's signature, or an edited activation
+        let forged = sign_activation(
+            &build_activation_request(&token, DEVICE).unwrap(),
+            &SigningKey::from_bytes(&[9; 32]),
+        );
+        assert!(verify(&forged, &token, DEVICE).is_err());
+        let (_, signature) = response.split_once('.').unwrap();
+        let edited = general_purpose::STANDARD.encode(format!(
+            r#"{{"license_id":"license-1","token_sha256":"{}","machine_id":"device-b","activated_at":"2025-06-01T00:00:00+00:00"}}"#,
+            sha256_hex(token.as_bytes())
+        ));
+        assert!(verify(&format!("{}.{}", edited, signature), &token, "device-b").is_err());
+        assert!(verify("garbage", &token, DEVICE).is_err());
+    }
+
+    #[test]
+    fn only_valid_licenses_export_an_activation_request() {
+        let expired = sign(&license("pro", Some(days_from_now(-60))));
+        assert_eq!(
+            build_activation_request(&expired, DEVICE),
+            Err(LicenseError::Expired.to_string())
+        );
+        assert!(build_activation_request("garbage", DEVICE).is_err());
+    }
+
+    fn capped_at(cap: &str) -> String {
+        let mut payload = license("pro", None);
+        payload["version_cap"] = json!(cap);
+        sign(&payload)
+    }
+
+    #[test]
+    fn versions_within_the_cap_are_covered() {
+        let v1 = capped_at("1");
+        for version in ["1.0.0", "1.9.3", "v1.2"] {
+            assert_eq!(
+                version_covered(&v1, DEVICE, version),
+                Some(true),
+                "{}",
+                version
+            );
+        }
+        let v1_4 = capped_at("1.4");
+        assert_eq!(version_covered(&v1_4, DEVICE, "1.4.7"), Some(true));
+        assert_eq!(version_covered(&v1_4, DEVICE, "0.9.0"), Some(true));
+
+        // No cap covers everything, including the running app
+        let uncapped = sign(&license("pro", None));
+        assert_eq!(version_covered(&uncapped, DEVICE, "99.0.0"), Some(true));
+        assert_eq!(
+            version_covered(&uncapped, DEVICE, env!("CARGO_PKG_VERSION")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn versions_beyond_the_cap_are_not() {
+        assert_eq!(
+            version_covered(&capped_at("1"), DEVICE, "2.0.0"),
+            Some(false)
+        );
+        assert_eq!(
+            version_covered(&capped_at("1.4"), DEVICE, "1.5.0"),
+            Some(false)
+        );
+
+        assert_eq!(version_covered(&capped_at("1.x"), DEVICE, "1.0.0"), None);
+        assert_eq!(version_covered(&capped_at("1"), DEVICE, "latest"), None);
+        assert_eq!(version_covered("garbage", DEVICE, "1.0.0"), None);
+    }
+
+    /// The outcome of every check, by name
+    fn checklist(
+        token: &str,
+        server: Option<&LicenseStatus>,
+        device_activated: Option<bool>,
+    ) -> BTreeMap<String, CheckOutcome> {
+        diagnose_license(token, DEVICE, server, device_activated)
+            .into_iter()
+            .map(|result| (result.check, result.outcome))
+            .collect()
+    }
+
+    /// The checks that failed
+    fn failing(checks: &BTreeMap<String, CheckOutcome>) -> Vec<&str> {
+        checks
+            .iter()
+            .filter(|(_, outcome)| **outcome == CheckOutcome::Fail)
+            .map(|(check, _)| check.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn sound_license_passes_every_check() {
+        let token = sign(&bound_to(license("pro", Some(days_from_now(30))), DEVICE));
+        let server = verify_license_token(&token, DEVICE);
+        let checks = checklist(&token, Some(&server), Some(true));
+        assert_eq!(checks.len(), 8);
+        assert!(failing(&checks).is_empty(), "{:?}", checks);
+        assert_eq!(checks["signature"], CheckOutcome::Pass);
+        assert_eq!(checks["revocation"], CheckOutcome::Pass);
+        assert_eq!(checks["device"], CheckOutcome::Pass);
+    }
+
+    #[test]
+    fn checklist_flags_the_failing_check() {
+        let checks = checklist("garbage", None, None);
+        assert_eq!(failing(&checks), vec!["format"]);
+        assert!(checks
+            .iter()
+            .all(|(check, outcome)| check == "format" || *outcome == CheckOutcome::Skip));
+
+        let payload = license("pro", None);
+        let forged = {
+            let token = sign(&payload);
+            let other = sign(&license("team", None));
+            format!(
+                "{}.{}",
+                token.split('.').next().unwrap(),
+                other.split('.').nth(1).unwrap()
+            )
+        };
+        assert_eq!(failing(&checklist(&forged, None, None)), vec!["signature"]);
+
+        let mut other_product = license("pro", None);
+        other_product["product_id"] = json!("otherapp");
+        assert_eq!(
+            failing(&checklist(&sign(&other_product), None, None)),
+            vec!["product"]
+        );
+
+        let lapsed = sign(&license("pro", Some(days_from_now(-60))));
+        assert_eq!(
+            failing(&checklist(&lapsed, None, None)),
+            vec!["expiry", "grace"]
+        );
+        let in_grace = checklist(&sign(&license("pro", Some(days_from_now(-2)))), None, None);
+        assert_eq!(failing(&in_grace), vec!["expiry"]);
+        assert_eq!(in_grace["grace"], CheckOutcome::Pass);
+
+        let token = sign(&payload);
+        let revoked = LicenseStatus {
+            valid: false,
+            error: Some(LicenseError::Revoked),
+            ..verify_license_token(&token, DEVICE)
+        };
+        assert_eq!(
+            failing(&checklist(&token, Some(&revoked), None)),
+            vec!["revocation"]
+        );
+        assert_eq!(
+            failing(&checklist(&token, None, Some(false))),
+            vec!["device"]
+        );
+        let elsewhere = sign(&bound_to(license("pro", None), "device-b"));
+        assert_eq!(
+            failing(&checklist(&elsewhere, None, Some(true))),
+            vec!["device"]
+        );
+    }
+
+    #[test]
+    fn environment_check_explains_demo_tokens() {
+        let demo_signed = sign(&license("pro", None));
+        // As a release build, which only has the real key, sees it
+        let release = verify_license_token_with_keys(&demo_signed, DEVICE, &[PUBLIC_KEY_BASE64]);
+        assert_eq!(
+            environment_check(&release, true).outcome,
+            CheckOutcome::Fail
+        );
+
+        let verified = verify_license_token(&demo_signed, DEVICE);
+        assert_eq!(
+            environment_check(&verified, true).outcome,
+            CheckOutcome::Pass
+        );
+        assert_eq!(
+            environment_check(&verified, false).outcome,
+            CheckOutcome::Skip
+        );
+        // Debug builds trust the demo key
+        assert_ne!(
+            checklist(&demo_signed, None, None)["env"],
+            CheckOutcome::Fail
+        );
+    }
+
+    #[test]
+    fn deep_links_round_trip_tokens() {
+        // A unicode email makes `+` and `/` likely in standard base64
+        let mut payload = license("pro", None);
+        payload["email"] = json!("zoë+qr@例え.jp");
+        for token in [sign(&payload), sign_raw(&payload)] {
+            let link = license_deep_link(&token).unwrap();
+            let encoded = link.strip_prefix("localendar://license?token=").unwrap();
+            assert!(
+                !encoded.contains(['+', '/', '=', '&', '#', '?']),
+                "{}",
+                link
+            );
+            assert_eq!(token_from_deep_link(&link).unwrap(), token);
+
+            // Extra parameters and surrounding whitespace from a scanner are ignored
+            let scanned = format!(" {}&source=qr\n", link);
+            assert_eq!(token_from_deep_link(&scanned).unwrap(), token);
+            assert!(verify_license_token(&token_from_deep_link(&link).unwrap(), DEVICE).valid);
+        }
+
+        assert!(license_deep_link("not a token").is_err());
+        assert!(token_from_deep_link("https://example.com/license?token=abc").is_err());
+        assert!(token_from_deep_link("localendar://license?source=qr").is_err());
+    }
+
+    #[test]
+    fn license_update_only_offers_a_better_license_for_the_same_customer() {
+        let stored = sign(&license("pro", Some(days_from_now(30))));
+        let perpetual = sign(&license("pro", None));
+        assert_eq!(
+            license_update(&stored, &perpetual, DEVICE),
+            Some(perpetual.clone())
+        );
+
+        // Nothing newer: the same license, or a worse one
+        assert_eq!(license_update(&stored, &stored, DEVICE), None);
+        assert_eq!(license_update(&perpetual, &stored, DEVICE), None);
+        let shorter = sign(&license("pro", Some(days_from_now(10))));
+        assert_eq!(license_update(&stored, &shorter, DEVICE), None);
+
+        // Someone else's license, or a forged one, is never offered
+        let mut other = license("pro", None);
+        other["email"] = json!("someone@example.com");
+        assert_eq!(license_update(&stored, &sign(&other), DEVICE), None);
+        let forged = format!("{}.{}", perpetual.split('.').next().unwrap(), "AAAA");
+        assert_eq!(license_update(&stored, &forged, DEVICE), None);
+        let elsewhere = sign(&bound_to(license("pro", None), "device-b"));
+        assert_eq!(license_update(&stored, &elsewhere, DEVICE), None);
+    }
+
+    #[test]
+    fn issue_date_is_shown_in_local_time() {
+        let mut payload = license("pro", None);
+        payload["issued_at"] = json!("2025-01-15T12:00:00+00:00");
+        let issued = license_issued_local(&sign(&payload), DEVICE).unwrap();
+        let expected = DateTime::parse_from_rfc3339("2025-01-15T12:00:00+00:00").unwrap();
+        assert_eq!(issued, format_local_date(&expected));
+        assert!(issued.starts_with("January 1"), "{}", issued);
+        assert!(issued.ends_with(", 2025"), "{}", issued);
+
+        payload["issued_at"] = json!("last Tuesday");
+        assert_eq!(license_issued_local(&sign(&payload), DEVICE), None);
+        assert_eq!(license_issued_local("not-a-token", DEVICE), None);
+        let elsewhere = sign(&bound_to(license("pro", None), "device-b"));
+        assert_eq!(license_issued_local(&elsewhere, DEVICE), None);
+    }
+
+    #[test]
+    fn matched_key_index_names_the_key_that_verified() {
+        let demo = public_key_of(DEMO_SIGNING_SEED);
+        let other = public_key_of(&[9; 32]);
+        let token = sign(&license("pro", None));
+
+        for (keys, index) in [
+            ([demo.as_str(), other.as_str()], 0),
+            ([other.as_str(), demo.as_str()], 1),
+        ] {
+            let status = verify_license_token_with_keys(&token, DEVICE, &keys);
+            assert!(status.valid);
+            assert_eq!(status.matched_key_index, Some(index));
+        }
+
+        // An expired license still says which key signed it
+        let expired = sign(&license("pro", Some(days_from_now(-400))));
+        let status =
+            verify_license_token_with_keys(&expired, DEVICE, &[other.as_str(), demo.as_str()]);
+        assert!(!status.valid);
+        assert_eq!(status.matched_key_index, Some(1));
+
+        for status in [
+            verify_license_token_with_keys(&token, DEVICE, &[other.as_str()]),
+            verify_license_token_with_keys("not-a-token", DEVICE, &[demo.as_str()]),
+        ] {
+            assert!(!status.valid);
+            assert_eq!(status.matched_key_index, None);
+        }
+    }
+
+    #[test]
+    fn debug_states_land_the_app_in_that_state() {
+        for (state, expected) in [
+            ("valid", LicensingState::Active),
+            ("expired", LicensingState::Expired),
+            ("grace", LicensingState::Grace),
+            ("tampered", LicensingState::Tampered),
+            ("none", LicensingState::NeverActivated),
+        ] {
+            let token = debug_state_token(state).unwrap();
+            assert_eq!(token.is_none(), state == "none");
+            assert_eq!(
+                licensing_state(token.as_deref(), DEVICE, None),
+                expected,
+                "{}",
+                state
+            );
+        }
+
+        let error = debug_state_token("revoked").unwrap_err();
+        assert!(error.contains("Unknown state 'revoked'"), "{}", error);
+    }
+
+    #[test]
+    fn product_allowlist_accepts_any_listed_product() {
+        let demo = public_key_of(DEMO_SIGNING_SEED);
+        let keys = [demo.as_str()];
+        let allowlist = ["localendar-mvp", "localendar-teams"];
+        let for_product = |product_id: &str| {
+            let mut payload = license("pro", None);
+            payload["product_id"] = json!(product_id);
+            sign(&payload)
+        };
+
+        for product_id in allowlist {
+            let status = verify_license_token_for_products(
+                &for_product(product_id),
+                DEVICE,
+                &keys,
+                &allowlist,
+            );
+            assert!(status.valid, "{}: {:?}", product_id, status.error);
+        }
+
+        let status =
+            verify_license_token_for_products(&for_product("other-app"), DEVICE, &keys, &allowlist);
+        assert!(!status.valid);
+        assert_eq!(
+            status.error,
+            Some(LicenseError::WrongProduct("other-app".to_string()))
+        );
+
+        // A single id behaves as before; an empty list accepts any product
+        let single = ["localendar-mvp"];
+        let teams = for_product("localendar-teams");
+        assert!(!verify_license_token_for_products(&teams, DEVICE, &keys, &single).valid);
+        assert!(verify_license_token_for_products(&teams, DEVICE, &keys, &[]).valid);
+    }
+
+    #[test]
+    fn support_code_is_stable_and_readable() {
+        let token = sign(&license("pro", None));
+        let code = support_code(&token, DEVICE).unwrap();
+        assert_eq!(support_code(&token, DEVICE), Some(code.clone()));
+        // However the token was pasted
+        assert_eq!(
+            support_code(&format!("  {}\n", token), DEVICE),
+            Some(code.clone())
+        );
+
+        let groups: Vec<&str> = code.split('-').collect();
+        assert_eq!(groups.len(), 3, "{}", code);
+        assert!(groups.iter().all(|group| group.len() == 4));
+        assert!(code
+            .chars()
+            .all(|c| c == '-' || SUPPORT_CODE_ALPHABET.contains(&(c as u8))));
+
+        // The check character covers the rest of the code
+        let data: String = code.chars().filter(|c| *c != '-').take(11).collect();
+        let check = SUPPORT_CODE_ALPHABET[(Sha256::digest(data.as_bytes())[0] >> 3) as usize];
+        assert_eq!(code.as_bytes()[13], check);
+
+        let other = sign(&license("pro", Some(days_from_now(30))));
+        assert_ne!(support_code(&other, DEVICE), Some(code));
+        assert_eq!(support_code("not-a-token", DEVICE), None);
+        let expired = sign(&license("pro", Some(days_from_now(-400))));
+        assert_eq!(support_code(&expired, DEVICE), None);
+    }
+
+    #[test]
+    fn view_model_for_a_pro_license() {
+        let expires_at = days_from_now(200);
+        let view = license_view_model(
+            &sign(&license("pro", Some(expires_at.clone()))),
+            DEVICE,
+            "1.0.0",
+        );
+        let expires = DateTime::parse_from_rfc3339(&expires_at).unwrap();
+        assert_eq!(
+            view,
+            LicenseViewModel {
+                status_label: "Active".to_string(),
+                plan_label: Some("Pro".to_string()),
+                expiry_display: Some(format!("Expires {}", format_local_date(&expires))),
+                severity: Severity::Ok,
+                is_trial: false,
+                in_grace: false,
+                days_remaining: Some(199),
+                needs_update: false,
+            }
+        );
+
+        let perpetual = license_view_model(&sign(&license("pro", None)), DEVICE, "1.0.0");
+        assert_eq!(perpetual.expiry_display.as_deref(), Some("Never expires"));
+        assert_eq!(perpetual.days_remaining, None);
+
+        let mut trial = license("pro", Some(days_from_now(10)));
+        trial["source"] = json!("trial");
+        trial["version_cap"] = json!("1.0.0");
+        let view = license_view_model(&sign(&trial), DEVICE, "2.0.0");
+        assert!(view.is_trial);
+        assert!(view.needs_update);
+    }
+
+    #[test]
+    fn view_model_for_an_expired_license() {
+        let expires_at = days_from_now(-400);
+        let view = license_view_model(
+            &sign(&license("pro", Some(expires_at.clone()))),
+            DEVICE,
+            "1.0.0",
+        );
+        let expires = DateTime::parse_from_rfc3339(&expires_at).unwrap();
+        assert_eq!(view.status_label, "Expired");
+        assert_eq!(view.plan_label, None);
+        assert_eq!(
+            view.expiry_display,
+            Some(format!("Expired {}", format_local_date(&expires)))
+        );
+        assert_eq!(view.severity, Severity::Critical);
+        assert!(!view.in_grace);
+        assert_eq!(view.days_remaining, Some(-400));
+
+        let view = license_view_model("not-a-token", DEVICE, "1.0.0");
+        assert_eq!(view.status_label, "Invalid");
+        assert_eq!(view.expiry_display, None);
+    }
+
+    #[test]
+    fn license_folder_reports_each_token_file() {
+        let dir = std::env::temp_dir().join(format!("licensing-folder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let valid = sign(&license("pro", None));
+        let expired = sign(&license("pro", Some(days_from_now(-400))));
+        let (payload_b64, _) = valid.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            payload_b64,
+            general_purpose::STANDARD.encode([0u8; 64])
+        );
+        std::fs::write(dir.join("a-valid.lic"), format!("{}\n", valid)).unwrap();
+        std::fs::write(dir.join("b-expired.lic"), &expired).unwrap();
+        std::fs::write(dir.join("c-forged.lic"), &forged).unwrap();
+        // Skipped: not a token, not text, too big, not a file
+        std::fs::write(dir.join("README.txt"), "Keep these safe").unwrap();
+        std::fs::write(dir.join("logo.png"), [0xff, 0xd8, 0x00, 0x9f]).unwrap();
+        let oversized = format!("{}{}", valid, " ".repeat(MAX_LICENSE_FILE_BYTES as usize));
+        std::fs::write(dir.join("padded.lic"), oversized).unwrap();
+        std::fs::write(dir.join("nested").join("d-valid.lic"), &valid).unwrap();
+
+        let results = verify_license_folder(&dir, DEVICE).unwrap();
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a-valid.lic", "b-expired.lic", "c-forged.lic"]);
+        assert!(results[0].1.valid);
+        assert!(!results[1].1.valid);
+        assert!(results[1].1.payload.is_some());
+        assert!(!results[2].1.valid);
+        assert_eq!(results[2].1.error, Some(LicenseError::BadSignature));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(verify_license_folder(&dir, DEVICE).is_err());
+    }
+
+    #[test]
+    fn duplicate_licenses_collapse_to_the_best_per_email() {
+        let monthly = sign(&license("pro", Some(days_from_now(30))));
+        let yearly = sign(&license("pro", Some(days_from_now(365))));
+        let mut shouting = license("basic", None);
+        shouting["email"] = json!("USER@example.com ");
+        let perpetual = sign(&shouting);
+        let mut other = license("pro", Some(days_from_now(30)));
+        other["email"] = json!("other@example.com");
+        let other = sign(&other);
+
+        let imported: Vec<String> = [
+            &monthly,
+            &other,
+            &yearly,
+            &monthly,
+            &format!("  {}\n", other),
+            &perpetual,
+            &"not-a-token".to_string(),
+        ]
+        .into_iter()
+        .cloned()
+        .collect();
+        assert_eq!(
+            merge_duplicate_licenses(&imported, DEVICE),
+            vec![perpetual.clone(), other.clone()]
+        );
+
+        // Without the perpetual license the longest expiry wins
+        assert_eq!(
+            merge_duplicate_licenses(&[monthly.clone(), yearly.clone(), monthly], DEVICE),
+            vec![yearly]
+        );
+        assert!(merge_duplicate_licenses(&["junk".to_string()], DEVICE).is_empty());
+    }
+
+    #[test]
+    fn expiry_is_checked_against_the_given_instant() {
+        let expires_at = "2026-06-30T12:00:00+00:00";
+        let token = sign(&license("pro", Some(expires_at.to_string())));
+        let expiry = DateTime::parse_from_rfc3339(expires_at)
+            .unwrap()
+            .with_timezone(&Utc);
+        let second = chrono::Duration::seconds(1);
+
+        let before = verify_license_token_at(&token, DEVICE, expiry - second);
+        assert!(before.valid);
+        assert!(!before.grace_period);
+        assert_eq!(before.expires_at.as_deref(), Some(expires_at));
+
+        // Past the instant the grace window opens, then closes
+        let after = verify_license_token_at(&token, DEVICE, expiry + second);
+        assert!(after.valid);
+        assert!(after.grace_period);
+        let beyond = expiry + chrono::Duration::days(GRACE_PERIOD_DAYS) + second;
+        let expired = verify_license_token_at(&token, DEVICE, beyond);
+        assert!(!expired.valid);
+        assert!(!expired.grace_period);
+        assert!(expired.payload.is_some());
+    }
+
+    #[test]
+    fn access_level_gates_writes_by_license_state() {
+        let access = |token: &str| access_level(Some(token), DEVICE, None);
+        let read_only_policy = |mut payload: serde_json::Value| {
+            payload["downgrade_on_expiry"] = json!(true);
+            payload
+        };
+
+        assert_eq!(access(&sign(&license("pro", None))), AccessLevel::Full);
+        let grace = license("pro", Some(days_from_now(-2)));
+        assert_eq!(access(&sign(&grace)), AccessLevel::Full);
+        let expired = license("pro", Some(days_from_now(-400)));
+        assert_eq!(
+            access(&sign(&read_only_policy(expired.clone()))),
+            AccessLevel::ReadOnly
+        );
+        assert_eq!(access(&sign(&expired)), AccessLevel::Locked);
+
+        assert_eq!(access_level(None, DEVICE, None), AccessLevel::Locked);
+        assert_eq!(access("not-a-token"), AccessLevel::Locked);
+        // A rolled-back clock locks even a valid license
+        let future = days_from_now(1);
+        assert_eq!(
+            access_level(Some(&sign(&license("pro", None))), DEVICE, Some(&future)),
+            AccessLevel::Locked
+        );
+    }
+
+    #[test]
+    fn remaining_seats_subtracts_activations_from_the_seat_count() {
+        let mut team = license("team", Some(days_from_now(30)));
+        team["seats"] = json!(5);
+        let team = sign(&team);
+        assert_eq!(remaining_seats(&team, DEVICE, 0), Ok(5));
+        assert_eq!(remaining_seats(&team, DEVICE, 2), Ok(3));
+        assert_eq!(remaining_seats(&team, DEVICE, 5), Ok(0));
+        // Over-activated licenses never go negative
+        assert_eq!(remaining_seats(&team, DEVICE, 7), Ok(0));
+
+        // Perpetual licenses without a seat count have one seat
+        let single = sign(&license("pro", None));
+        assert_eq!(remaining_seats(&single, DEVICE, 0), Ok(1));
+        assert_eq!(remaining_seats(&single, DEVICE, 1), Ok(0));
+
+        let expired = sign(&license("team", Some(days_from_now(-30))));
+        assert!(remaining_seats(&expired, DEVICE, 0).is_err());
+        assert!(remaining_seats("not-a-token", DEVICE, 0).is_err());
+    }
+
+    /// Every token in `test-vectors/generated-tokens.json`: generated
+    /// emails, plans and expiries, signed by the server's `sign_license`
+    #[test]
+    fn server_signed_generated_tokens_verify_or_expire() {
+        let vectors: serde_json::Value =
+            serde_json::from_str(include_str!("../../test-vectors/generated-tokens.json")).unwrap();
+        let public_key = token_vectors()["key"]["public_key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let now = DateTime::parse_from_rfc3339(vectors["now"].as_str().unwrap())
+            .unwrap()
+            .with_timezone(&Utc);
+        let tokens = vectors["tokens"].as_array().unwrap();
+        assert_eq!(tokens.len(), 5000);
+
+        for token in tokens {
+            let token = token.as_str().unwrap();
+            // The claims as signed, read without the verifier
+            let (payload_b64, _) = token.trim_start_matches("v2.").split_once('.').unwrap();
+            let claims: LicensePayload =
+                serde_json::from_slice(&general_purpose::STANDARD.decode(payload_b64).unwrap())
+                    .unwrap();
+            let expires_at = claims
+                .expires_at
+                .as_deref()
+                .map(|at| DateTime::parse_from_rfc3339(at).unwrap());
+            let grace_days = claims.grace_days.unwrap();
+            let lapsed = expires_at.is_some_and(|at| at + chrono::Duration::days(grace_days) < now);
+
+            let status = verify_with_key_at(token, &public_key, now);
+            assert_eq!(status.valid, !lapsed, "{:?} {:?}", claims, status);
+            if lapsed {
+                assert_eq!(status.error, Some(LicenseError::Expired), "{:?}", claims);
+            } else {
+                assert_eq!(
+                    status.grace_period,
+                    expires_at.is_some_and(|at| at < now),
+                    "{:?}",
+                    claims
+                );
+                assert_eq!(status.payload, Some(claims));
+            }
+        }
+    }
+
+    #[test]
+    fn demo_tokens_are_explained_without_the_demo_key() {
+        let payload = license("pro", None);
+        let zero_signed = format!(
+            "{}.{}",
+            general_purpose::STANDARD.encode(payload.to_string()),
+            general_purpose::STANDARD.encode([0u8; SIGNATURE_LENGTH])
+        );
+        let demo_signed = sign(&payload);
+
+        // A release build only has the real key
+        for token in [&zero_signed, &demo_signed] {
+            let status = verify_license_token_with_keys(token, DEVICE, &[PUBLIC_KEY_BASE64]);
+            assert!(!status.valid);
+            assert_eq!(status.error, Some(LicenseError::DemoToken));
+            assert_eq!(
+                status.error.unwrap().to_string(),
+                "Debug demo token not valid in release build"
+            );
+        }
+
+        // Debug builds trust the demo key; the zero placeholder is just a bad signature
+        assert!(verify_license_token(&demo_signed, DEVICE).valid);
+        assert_eq!(
+            verify_license_token(&zero_signed, DEVICE).error,
+            Some(LicenseError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn offline_verification_needs_a_real_public_key() {
+        // An unconfigured debug build: the placeholder and the demo key
+        assert!(!has_real_public_key(&[
+            PLACEHOLDER_PUBLIC_KEY,
+            DEMO_PUBLIC_KEY_BASE64
+        ]));
+        assert!(!has_real_public_key(&["not base64!", "c2hvcnQ="]));
+        assert!(!has_real_public_key(&[]));
+
+        let configured = public_key_of(&[3; 32]);
+        assert!(has_real_public_key(&[&configured]));
+        assert!(has_real_public_key(&[
+            PLACEHOLDER_PUBLIC_KEY,
+            &configured,
+            DEMO_PUBLIC_KEY_BASE64
+        ]));
+    }
+
+    #[test]
+    fn reverification_falls_due_with_the_offline_window() {
+        let now = Utc::now();
+        let ago = |days: i64| Some((now - chrono::Duration::days(days)).to_rfc3339());
+        let perpetual = sign(&license("pro", None));
+
+        // Just verified: due a full offline window later
+        let due = next_reverification_due(&perpetual, DEVICE, ago(0).as_deref(), now);
+        assert!(due > now + chrono::Duration::days(OFFLINE_WINDOW_DAYS - 1));
+        assert!(due <= now + chrono::Duration::days(OFFLINE_WINDOW_DAYS));
+
+        // Stale, never verified, or unreadable: due now
+        let stale = ago(OFFLINE_WINDOW_DAYS + 3);
+        assert_eq!(
+            next_reverification_due(&perpetual, DEVICE, stale.as_deref(), now),
+            now
+        );
+        assert_eq!(next_reverification_due(&perpetual, DEVICE, None, now), now);
+        assert_eq!(
+            next_reverification_due(&perpetual, DEVICE, Some("yesterday"), now),
+            now
+        );
+        assert_eq!(
+            next_reverification_due("not-a-token", DEVICE, ago(0).as_deref(), now),
+            now
+        );
+
+        // Near expiry the check comes daily, and never after the expiry itself
+        let expiring = sign(&license("pro", Some(days_from_now(3))));
+        let due = next_reverification_due(&expiring, DEVICE, ago(0).as_deref(), now);
+        assert!(due <= now + chrono::Duration::days(NEAR_EXPIRY_RECHECK_DAYS));
+        let expiring = sign(&license(
+            "pro",
+            Some((now + chrono::Duration::hours(2)).to_rfc3339()),
+        ));
+        let due = next_reverification_due(&expiring, DEVICE, ago(0).as_deref(), now);
+        assert!(due <= now + chrono::Duration::hours(2));
+    }
+
+    #[test]
+    fn try_use_feature_grants_or_explains_the_denial() {
+        let pro = sign(&license("pro", None));
+        assert_eq!(try_use_feature(&pro, DEVICE, "export"), Ok(()));
+        // Plans include the features of the plans below them
+        assert_eq!(try_use_feature(&pro, DEVICE, "view"), Ok(()));
+
+        let denied = try_use_feature(&pro, DEVICE, "shared_calendars").unwrap_err();
+        assert_eq!(
+            denied,
+            FeatureDenied {
+                feature: "shared_calendars".to_string(),
+                required_plan: Some("team".to_string()),
+                current_plan: Some("pro".to_string()),
+                reason: "Requires the team plan".to_string(),
+            }
+        );
+        // The `feature-denied` event carries it as-is
+        assert_eq!(
+            serde_json::to_value(&denied).unwrap(),
+            json!({
+                "feature": "shared_calendars",
+                "required_plan": "team",
+                "current_plan": "pro",
+                "reason": "Requires the team plan",
+            })
+        );
+
+        let unknown = try_use_feature(&pro, DEVICE, "teleport").unwrap_err();
+        assert_eq!(unknown.required_plan, None);
+        assert_eq!(unknown.reason, "Unknown feature 'teleport'");
+
+        let expired = sign(&license("pro", Some(days_from_now(-60))));
+        let denied = try_use_feature(&expired, DEVICE, "export").unwrap_err();
+        assert_eq!(denied.current_plan, None);
+        assert_eq!(denied.required_plan.as_deref(), Some("pro"));
+        assert_eq!(denied.reason, LicenseError::Expired.to_string());
+    }
+
+    #[test]
+    fn terms_version_round_trips_and_is_compared_to_the_current_terms() {
+        let mut accepted = license("pro", None);
+        accepted["terms_version"] = json!("2025-01");
+        let accepted = sign(&accepted);
+        let status = verify_license_token(&accepted, DEVICE);
+        assert_eq!(
+            status.payload.unwrap().terms_version.as_deref(),
+            Some("2025-01")
+        );
+        assert_eq!(
+            terms_acceptance_needed(&accepted, DEVICE, "2025-01"),
+            Some(false)
+        );
+        assert_eq!(
+            terms_acceptance_needed(&accepted, DEVICE, " 2025-01\n"),
+            Some(false)
+        );
+        assert_eq!(
+            terms_acceptance_needed(&accepted, DEVICE, "2026-03"),
+            Some(true)
+        );
+
+        // Licenses from before terms were recorded always ask
+        let old = sign(&license("pro", None));
+        assert_eq!(
+            verify_license_token(&old, DEVICE)
+                .payload
+                .unwrap()
+                .terms_version,
+            None
+        );
+        assert_eq!(terms_acceptance_needed(&old, DEVICE, "2025-01"), Some(true));
+
+        assert_eq!(
+            terms_acceptance_needed("not-a-token", DEVICE, "2025-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn anonymous_id_is_stable_per_license_and_hides_the_email() {
+        let token = sign(&license("pro", None));
+        let id = anonymous_id(&token, DEVICE).unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(anonymous_id(&token, DEVICE).as_deref(), Some(id.as_str()));
+        // Canonically the same token, however it was pasted
+        assert_eq!(
+            anonymous_id(&format!("  {}\n", token), DEVICE).as_deref(),
+            Some(id.as_str())
+        );
+        // A renewed token for the same customer keeps the id
+        let renewed = sign(&license("team", Some(days_from_now(365))));
+        assert_eq!(anonymous_id(&renewed, DEVICE).as_deref(), Some(id.as_str()));
+        assert_eq!(
+            anonymous_id_for(" User@Example.com ", EXPECTED_PRODUCT_ID),
+            id
+        );
+
+        let mut other = license("pro", None);
+        other["email"] = json!("other@example.com");
+        assert_ne!(anonymous_id(&sign(&other), DEVICE).unwrap(), id);
+        assert_ne!(anonymous_id_for("user@example.com", "another-product"), id);
+        assert_ne!(id, sha256_hex(b"user@example.com")[..32]);
+
+        assert_eq!(
+            anonymous_id(&sign(&license("pro", Some(days_from_now(-60)))), DEVICE),
+            None
+        );
+        assert_eq!(anonymous_id("not-a-token", DEVICE), None);
+    }
+
+    #[test]
+    fn grace_period_boundaries_are_inclusive() {
+        let expires_at = "2026-06-30T12:00:00+00:00";
+        let expiry = DateTime::parse_from_rfc3339(expires_at)
+            .unwrap()
+            .with_timezone(&Utc);
+        let grace_end = expiry + chrono::Duration::days(GRACE_PERIOD_DAYS);
+        let second = chrono::Duration::seconds(1);
+        let token = sign(&license("pro", Some(expires_at.to_string())));
+
+        // Exactly at expiry the license hasn't lapsed yet
+        let at_expiry = verify_license_token_at(&token, DEVICE, expiry);
+        assert!(at_expiry.valid);
+        assert!(!at_expiry.grace_period);
+
+        // Exactly at the end of grace it still works, a second later it doesn't
+        let at_grace_end = verify_license_token_at(&token, DEVICE, grace_end);
+        assert!(at_grace_end.valid);
+        assert!(at_grace_end.grace_period);
+        let past_grace = verify_license_token_at(&token, DEVICE, grace_end + second);
+        assert!(!past_grace.valid);
+        assert!(!past_grace.grace_period);
+        assert_eq!(past_grace.error, Some(LicenseError::Expired));
+
+        // A token's own grace_days moves the end of grace
+        let mut short = license("pro", Some(expires_at.to_string()));
+        short["grace_days"] = json!(3);
+        let short = sign(&short);
+        let three_days = expiry + chrono::Duration::days(3);
+        assert!(verify_license_token_at(&short, DEVICE, three_days).grace_period);
+        assert!(!verify_license_token_at(&short, DEVICE, three_days + second).valid);
+    }
+
+    #[test]
+    fn license_errors_serialize_as_stable_messages() {
+        let errors = [
+            (LicenseError::InvalidFormat, "Invalid token format"),
+            (LicenseError::DecodeFailed, "Failed to decode token"),
+            (LicenseError::BadSignature, "Signature verification failed"),
+            (LicenseError::Expired, "License has expired"),
+            (LicenseError::Revoked, "License revoked"),
+            (
+                LicenseError::WrongProduct("other-app".to_string()),
+                "Wrong product: this license is for 'other-app'",
+            ),
+            (
+                LicenseError::UnknownPlan("gold".to_string()),
+                "Unknown plan 'gold'",
+            ),
+            (
+                LicenseError::PlanTooLow("team".to_string()),
+                "Plan too low: requires team or higher",
+            ),
+            (LicenseError::SeatsExhausted(5), "All 5 seats are in use"),
+        ];
+        for (error, message) in errors {
+            assert_eq!(error.to_string(), message);
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json, json!(message));
+            assert_eq!(serde_json::from_value::<LicenseError>(json).unwrap(), error);
+        }
+
+        // Every variant survives the round trip
+        for error in [
+            LicenseError::InvalidPayload,
+            LicenseError::InvalidPublicKey,
+            LicenseError::UnknownKey,
+            LicenseError::DemoToken,
+            LicenseError::WrongDomain,
+            LicenseError::WrongDevice,
+            LicenseError::ServerMismatch,
+            LicenseError::RejectedByServer,
+        ] {
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(serde_json::from_value::<LicenseError>(json).unwrap(), error);
+        }
+
+        // Messages this build doesn't know are kept as they are
+        let unknown: LicenseError = serde_json::from_value(json!("Server on fire")).unwrap();
+        assert_eq!(unknown, LicenseError::Other("Server on fire".to_string()));
+        assert_eq!(unknown.to_string(), "Server on fire");
+    }
+
+    #[test]
+    fn days_remaining_is_floored_whole_days() {
+        let now = DateTime::parse_from_rfc3339("2026-06-30T09:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+        let remaining = |expires_at: DateTime<Utc>| {
+            let token = sign(&license("pro", Some(expires_at.to_rfc3339())));
+            verify_license_token_at(&token, DEVICE, now).days_remaining
+        };
+        let hours = chrono::Duration::hours;
+
+        // Later today is 0 days; exactly 24 hours out is 1
+        assert_eq!(remaining(now + hours(5)), Some(0));
+        assert_eq!(remaining(now + hours(24)), Some(1));
+        assert_eq!(
+            remaining(now + hours(24) - chrono::Duration::seconds(1)),
+            Some(0)
+        );
+        assert_eq!(remaining(now + hours(24 * 12 + 3)), Some(12));
+        // Negative while in grace
+        assert_eq!(remaining(now - hours(1)), Some(-1));
+        assert_eq!(remaining(now - hours(48)), Some(-2));
+
+        assert_eq!(
+            verify_license_token_at(&sign(&license("pro", None)), DEVICE, now).days_remaining,
+            None
+        );
+        // An expiry that doesn't parse is no days, not a panic
+        let garbled = sign(&license("pro", Some("next tuesday".to_string())));
+        assert_eq!(
+            verify_license_token_at(&garbled, DEVICE, now).days_remaining,
+            None
+        );
+    }
+
+    #[test]
+    fn only_licenses_for_this_product_verify() {
+        let correct = sign(&license("pro", None));
+        let status = verify_license_token(&correct, DEVICE);
+        assert!(status.valid);
+        assert_eq!(status.payload.unwrap().product_id, EXPECTED_PRODUCT_ID);
+        assert!(product_accepted(EXPECTED_PRODUCT_ID));
+
+        // Same key, another app's license
+        let mut other = license("pro", None);
+        other["product_id"] = json!("other-app");
+        let status = verify_license_token(&sign(&other), DEVICE);
+        assert!(!status.valid);
+        assert_eq!(
+            status.error,
+            Some(LicenseError::WrongProduct("other-app".to_string()))
+        );
+        assert_eq!(
+            status.error.unwrap().to_string(),
+            "Wrong product: this license is for 'other-app'"
+        );
+        assert!(!product_accepted("other-app"));
+    }
+
+    #[test]
+    fn debug_builds_fall_back_to_the_placeholder_key() {
+        let key = "A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=";
+        assert_eq!(public_key_or_placeholder(Some(key)), key);
+        assert_eq!(public_key_or_placeholder(None), PLACEHOLDER_PUBLIC_KEY);
+        // The placeholder decodes but verifies nothing
+        assert_eq!(
+            general_purpose::STANDARD
+                .decode(PLACEHOLDER_PUBLIC_KEY)
+                .unwrap(),
+            [0u8; PUBLIC_KEY_LENGTH]
+        );
+        assert!(!has_real_public_key(&[PLACEHOLDER_PUBLIC_KEY]));
+        let token = sign(&license("pro", None));
+        assert!(!verify_license_token_with_keys(&token, DEVICE, &[PLACEHOLDER_PUBLIC_KEY]).valid);
+    }
 }
@@ -0,0 +1,691 @@
+use crate::licensing::{
+    apply_server_verdict, bind_action, normalize_token, seats_exhausted, verified_server_time,
+    BindAction, LicenseStatus, ServerVerifyResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a fetched activation list is reused before asking the server again
+const ACTIVATION_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// The license server this build talks to for trust decisions (key pinning),
+// baked in at compile time from `LOCALENDAR_LICENSE_SERVER_URL` so the
+// webview can't point it elsewhere. Defaults to a local dev server.
+pub const LICENSE_SERVER_URL: &str = match option_env!("LOCALENDAR_LICENSE_SERVER_URL") {
+    Some(url) => url,
+    None => "http://localhost:3001",
+};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Activation {
+    pub machine_id: String,
+    pub activated_at: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ActivationsResponse {
+    pub license_id: String,
+    pub activations: Vec<Activation>,
+}
+
+/// Recent `/activations` answers, keyed by token
+#[derive(Default)]
+pub struct ActivationCache {
+    entries: Mutex<HashMap<String, (Vec<Activation>, Instant)>>,
+}
+
+impl ActivationCache {
+    pub fn get(&self, token: &str) -> Option<Vec<Activation>> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .get(token)
+            .filter(|(_, cached_at)| cached_at.elapsed() < ACTIVATION_CACHE_TTL)
+            .map(|(activations, _)| activations.clone())
+    }
+
+    pub fn insert(&self, token: &str, activations: Vec<Activation>) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(token.to_string(), (activations, Instant::now()));
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    /// The license's activations, from the cache or else the server
+    pub async fn activations(
+        &self,
+        base_url: &str,
+        token: &str,
+    ) -> Result<Vec<Activation>, String> {
+        if let Some(activations) = self.get(token) {
+            return Ok(activations);
+        }
+        let activations = fetch_activations(base_url, token).await?.activations;
+        self.insert(token, activations.clone());
+        Ok(activations)
+    }
+
+    /// Whether `machine_id` is among the license's activations
+    pub async fn is_activated(
+        &self,
+        base_url: &str,
+        token: &str,
+        machine_id: &str,
+    ) -> Result<bool, String> {
+        let activations = self.activations(base_url, token).await?;
+        Ok(activations
+            .iter()
+            .any(|activation| activation.machine_id == machine_id))
+    }
+}
+
+fn endpoint(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path)
+}
+
+/// The license server reports errors as plain-text bodies
+async fn error_from(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.text().await {
+        Ok(body) if !body.is_empty() => format!("License server error ({}): {}", status, body),
+        _ => format!("License server error ({})", status),
+    }
+}
+
+/// Fetch the devices the server has recorded as activated for this license
+pub async fn fetch_activations(base_url: &str, token: &str) -> Result<ActivationsResponse, String> {
+    let response = reqwest::Client::new()
+        .post(endpoint(base_url, "activations"))
+        .json(&serde_json::json!({ "token": token }))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from license server: {}", e))
+}
+
+/// Activate this license on `machine_id`, returning the updated activation
+/// list. `None` when the server refused because every seat is taken.
+pub async fn activate(
+    base_url: &str,
+    token: &str,
+    machine_id: &str,
+) -> Result<Option<ActivationsResponse>, String> {
+    let response = reqwest::Client::new()
+        .post(endpoint(base_url, "activate"))
+        .json(&serde_json::json!({ "token": normalize_token(token), "machine_id": machine_id }))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    response
+        .json()
+        .await
+        .map(Some)
+        .map_err(|e| format!("Invalid response from license server: {}", e))
+}
+
+/// The online half of `verify_and_bind`: if `status` is a valid seat-limited
+/// license, make sure `machine_id` holds one of its seats, activating it when
+/// one is free. Every seat taken turns `status` invalid; an unreachable server
+/// leaves it as is, so binding is retried later.
+pub async fn bind_seat(
+    status: LicenseStatus,
+    token: &str,
+    machine_id: &str,
+    base_url: &str,
+    cache: &ActivationCache,
+) -> LicenseStatus {
+    let Some(payload) = status.payload.as_ref().filter(|_| status.valid) else {
+        return status;
+    };
+    let activated: Vec<String> = match payload.seats {
+        None => Vec::new(),
+        Some(_) => match cache.activations(base_url, token).await {
+            Ok(activations) => activations.into_iter().map(|a| a.machine_id).collect(),
+            Err(_) => return status,
+        },
+    };
+    match bind_action(payload, &activated, machine_id) {
+        BindAction::Unbound | BindAction::AlreadyBound => status,
+        BindAction::SeatsExhausted => seats_exhausted(status),
+        BindAction::Bind => match activate(base_url, token, machine_id).await {
+            Ok(Some(response)) => {
+                cache.insert(token, response.activations);
+                status
+            }
+            // The server holds the seat count too, and another machine got
+            // the last seat first
+            Ok(None) => seats_exhausted(status),
+            Err(_) => status,
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LatestLicenseResponse {
+    token: Option<String>,
+}
+
+/// Ask the server for the customer's best active license. `None` when the
+/// token we hold is already the best one.
+pub async fn fetch_latest_license(base_url: &str, token: &str) -> Result<Option<String>, String> {
+    let response = reqwest::Client::new()
+        .post(endpoint(base_url, "latest-license"))
+        .json(&serde_json::json!({ "token": normalize_token(token) }))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    let body: LatestLicenseResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from license server: {}", e))?;
+    Ok(body.token)
+}
+
+/// The license server's signing key, as `/pubkey` serves it
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct PublicKeyResponse {
+    pub public_key: String,
+    /// The outgoing key's signature over the new one after a rotation
+    #[serde(default)]
+    pub endorsement: Option<String>,
+}
+
+/// Fetch the base64 public key the license server signs with
+pub async fn fetch_public_key(base_url: &str) -> Result<PublicKeyResponse, String> {
+    let response = reqwest::Client::new()
+        .get(endpoint(base_url, "pubkey"))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from license server: {}", e))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationsResponse {
+    revocations: HashSet<String>,
+}
+
+/// Fetch the revocation ids (see `licensing::revocation_id`) of every
+/// revoked license
+pub async fn fetch_revocations(base_url: &str) -> Result<HashSet<String>, String> {
+    let response = reqwest::Client::new()
+        .get(endpoint(base_url, "revocations"))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    let body: RevocationsResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from license server: {}", e))?;
+    Ok(body.revocations)
+}
+
+/// Verify a token against the license server. The result is cross-checked
+/// with local verification, so a lying or spoofed server can't validate a
+/// token the bundled public key rejects. Also returns the server's signed
+/// clock, when it sent one that verifies.
+pub async fn verify_online(
+    base_url: &str,
+    token: &str,
+    fingerprint: &str,
+) -> Result<(LicenseStatus, Option<DateTime<Utc>>), String> {
+    let token = normalize_token(token);
+    let response = reqwest::Client::new()
+        .post(endpoint(base_url, "verify-license"))
+        .json(&serde_json::json!({ "token": token }))
+        .send()
+        .await
+        .map_err(|e| format!("License server unreachable: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+
+    let server: ServerVerifyResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from license server: {}", e))?;
+
+    Ok((
+        apply_server_verdict(token, fingerprint, &server),
+        verified_server_time(&server),
+    ))
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::licensing::{
+        generate_demo_license, verify_license_token, LicenseError, LicensePayload,
+    };
+    use base64::{engine::general_purpose, Engine as _};
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A local server answering every request with `status` and `body`.
+    /// Returns its base URL and a count of the requests it has served.
+    fn mock_server(status: u16, body: &str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        let response = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                read_request(&mut stream);
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (base_url, served)
+    }
+
+    /// Read one request (headers and a `Content-Length` body) off `stream`
+    fn read_request(stream: &mut std::net::TcpStream) {
+        let mut request = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let Ok(read) = stream.read(&mut chunk) else {
+                return;
+            };
+            if read == 0 {
+                return;
+            }
+            request.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// An address nothing listens on
+    fn unreachable_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn activations_body(machine_ids: &[&str]) -> String {
+        let activations: Vec<_> = machine_ids
+            .iter()
+            .map(|id| serde_json::json!({ "machine_id": id, "activated_at": "2025-01-15T00:00:00Z" }))
+            .collect();
+        serde_json::json!({ "license_id": "lic-1", "activations": activations }).to_string()
+    }
+
+    #[tokio::test]
+    async fn device_on_the_activation_list_is_activated() {
+        let (base_url, _) = mock_server(200, &activations_body(&["other", "this-device"]));
+        let cache = ActivationCache::default();
+        assert_eq!(
+            cache.is_activated(&base_url, "token", "this-device").await,
+            Ok(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn device_missing_from_the_activation_list_is_not_activated() {
+        let (base_url, _) = mock_server(200, &activations_body(&["other"]));
+        let cache = ActivationCache::default();
+        assert_eq!(
+            cache.is_activated(&base_url, "token", "this-device").await,
+            Ok(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn activation_answers_are_cached_per_token() {
+        let (base_url, served) = mock_server(200, &activations_body(&["this-device"]));
+        let cache = ActivationCache::default();
+        for _ in 0..3 {
+            assert_eq!(
+                cache.is_activated(&base_url, "token", "this-device").await,
+                Ok(true)
+            );
+        }
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        cache
+            .is_activated(&base_url, "other-token", "this-device")
+            .await
+            .unwrap();
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+        cache.clear();
+        cache
+            .is_activated(&base_url, "token", "this-device")
+            .await
+            .unwrap();
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn activation_check_reports_server_errors() {
+        let (base_url, _) = mock_server(400, "Invalid license");
+        let error = ActivationCache::default()
+            .is_activated(&base_url, "token", "this-device")
+            .await
+            .unwrap_err();
+        assert!(error.contains("400"), "{}", error);
+
+        let error = ActivationCache::default()
+            .is_activated(&unreachable_server(), "token", "this-device")
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("License server unreachable"), "{}", error);
+    }
+
+    /// What `/verify-license` would answer about `payload`
+    fn verdict(valid: bool, payload: Option<&LicensePayload>) -> String {
+        serde_json::json!({
+            "valid": valid,
+            "payload": payload,
+            "expires_at": payload.and_then(|p| p.expires_at.clone()),
+            "expired": false,
+            "error": if valid { None } else { Some("License revoked") },
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn server_cannot_validate_a_token_the_app_rejects() {
+        let genuine = generate_demo_license("user@example.com");
+        let payload = verify_license_token(&genuine, "this-device")
+            .payload
+            .unwrap();
+
+        // Same claims, signed by a key the app doesn't trust
+        let payload_b64 =
+            general_purpose::STANDARD.encode(serde_json::to_string(&payload).unwrap());
+        let signature = SigningKey::from_bytes(&[9; 32]).sign(payload_b64.as_bytes());
+        let forged = format!(
+            "{}.{}",
+            payload_b64,
+            general_purpose::STANDARD.encode(signature.to_bytes())
+        );
+
+        let (base_url, _) = mock_server(200, &verdict(true, Some(&payload)));
+        let (status, _) = verify_online(&base_url, &forged, "this-device")
+            .await
+            .unwrap();
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::BadSignature));
+
+        let (status, _) = verify_online(&base_url, "junk", "this-device")
+            .await
+            .unwrap();
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::InvalidFormat));
+    }
+
+    #[tokio::test]
+    async fn server_describing_another_license_is_rejected() {
+        let token = generate_demo_license("user@example.com");
+        let mut payload = verify_license_token(&token, "this-device").payload.unwrap();
+        payload.plan = "team".to_string();
+
+        let (base_url, _) = mock_server(200, &verdict(true, Some(&payload)));
+        let (status, _) = verify_online(&base_url, &token, "this-device")
+            .await
+            .unwrap();
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::ServerMismatch));
+    }
+
+    #[tokio::test]
+    async fn server_can_revoke_a_genuine_token() {
+        let token = generate_demo_license("user@example.com");
+        let payload = verify_license_token(&token, "this-device").payload.unwrap();
+
+        let (base_url, _) = mock_server(200, &verdict(true, Some(&payload)));
+        let (status, _) = verify_online(&base_url, &token, "this-device")
+            .await
+            .unwrap();
+        assert!(status.valid, "{:?}", status.error);
+
+        let (base_url, _) = mock_server(200, &verdict(false, Some(&payload)));
+        let (status, _) = verify_online(&base_url, &token, "this-device")
+            .await
+            .unwrap();
+        assert!(!status.valid);
+        assert_eq!(status.error, Some(LicenseError::Revoked));
+    }
+
+    #[tokio::test]
+    async fn fresh_verification_returns_the_server_answer() {
+        let token = generate_demo_license("user@example.com");
+        let payload = verify_license_token(&token, "this-device").payload.unwrap();
+
+        let (base_url, served) = mock_server(200, &verdict(true, Some(&payload)));
+        for _ in 0..2 {
+            let (status, _) = verify_online(&base_url, &token, "this-device")
+                .await
+                .unwrap();
+            assert!(status.valid, "{:?}", status.error);
+        }
+        // Nothing is cached between calls
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_an_error_not_a_fallback() {
+        let token = generate_demo_license("user@example.com");
+        let error = verify_online(&unreachable_server(), &token, "this-device")
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("License server unreachable"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn fetched_public_key_is_read_from_pubkey() {
+        let key = "A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=";
+        let (base_url, served) = mock_server(200, &format!(r#"{{"public_key":"{}"}}"#, key));
+        assert_eq!(
+            fetch_public_key(&base_url).await,
+            Ok(PublicKeyResponse {
+                public_key: key.to_string(),
+                endorsement: None,
+            })
+        );
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let body = format!(r#"{{"public_key":"{}","endorsement":"c2ln"}}"#, key);
+        let (base_url, _) = mock_server(200, &body);
+        assert_eq!(
+            fetch_public_key(&base_url)
+                .await
+                .unwrap()
+                .endorsement
+                .as_deref(),
+            Some("c2ln")
+        );
+
+        let (base_url, _) = mock_server(500, "boom");
+        assert!(fetch_public_key(&base_url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn latest_license_is_read_from_the_server() {
+        let (base_url, served) = mock_server(200, r#"{"token":"newer.token"}"#);
+        assert_eq!(
+            fetch_latest_license(&base_url, "stored.token").await,
+            Ok(Some("newer.token".to_string()))
+        );
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        // Already holding the best license
+        let (base_url, _) = mock_server(200, r#"{"token":null}"#);
+        assert_eq!(
+            fetch_latest_license(&base_url, "stored.token").await,
+            Ok(None)
+        );
+
+        let (base_url, _) = mock_server(403, "Invalid license");
+        assert!(fetch_latest_license(&base_url, "stored.token")
+            .await
+            .is_err());
+    }
+
+    /// A valid demo license with `seats` seats, or no seat count
+    fn seat_license(seats: Option<u32>) -> (String, LicenseStatus) {
+        let mut payload = verify_license_token(&generate_demo_license("team@example.com"), "")
+            .payload
+            .unwrap();
+        payload.plan = "team".to_string();
+        payload.seats = seats;
+        let token = crate::licensing::sign_demo_token(&payload);
+        let status = verify_license_token(&token, "this-device");
+        assert!(status.valid);
+        (token, status)
+    }
+
+    #[tokio::test]
+    async fn unbound_licenses_are_only_verified() {
+        let (base_url, served) = mock_server(200, &activations_body(&[]));
+        let (token, status) = seat_license(None);
+        let bound = bind_seat(
+            status,
+            &token,
+            "this-device",
+            &base_url,
+            &ActivationCache::default(),
+        )
+        .await;
+        assert!(bound.valid, "{:?}", bound.error);
+        assert_eq!(served.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn a_free_seat_is_taken_on_first_use() {
+        // The mock answers `/activations` and `/activate` alike; the list
+        // holds another machine, so a seat is free for this one
+        let (base_url, served) = mock_server(200, &activations_body(&["other"]));
+        let (token, status) = seat_license(Some(2));
+        let cache = ActivationCache::default();
+        let bound = bind_seat(status.clone(), &token, "this-device", &base_url, &cache).await;
+        assert!(bound.valid, "{:?}", bound.error);
+        // Listed, then activated
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+
+        // Already bound: nothing more to do
+        cache.insert(
+            &token,
+            serde_json::from_str::<ActivationsResponse>(&activations_body(&[
+                "other",
+                "this-device",
+            ]))
+            .unwrap()
+            .activations,
+        );
+        let bound = bind_seat(status, &token, "this-device", &base_url, &cache).await;
+        assert!(bound.valid, "{:?}", bound.error);
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn every_seat_taken_is_seats_exhausted() {
+        let (base_url, served) = mock_server(200, &activations_body(&["other", "another"]));
+        let (token, status) = seat_license(Some(2));
+        let bound = bind_seat(
+            status,
+            &token,
+            "this-device",
+            &base_url,
+            &ActivationCache::default(),
+        )
+        .await;
+        assert!(!bound.valid);
+        assert_eq!(bound.error, Some(LicenseError::SeatsExhausted(2)));
+        // Nothing was activated
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        // Unreachable: left as verified offline, to bind on a later launch
+        let (token, status) = seat_license(Some(2));
+        let bound = bind_seat(
+            status,
+            &token,
+            "this-device",
+            &unreachable_server(),
+            &ActivationCache::default(),
+        )
+        .await;
+        assert!(bound.valid, "{:?}", bound.error);
+    }
+
+    #[tokio::test]
+    async fn a_seat_the_server_refuses_is_seats_exhausted() {
+        // The cached list shows a free seat, but the server answers `/activate`
+        // with a 409
+        let (base_url, served) = mock_server(409, "All 2 seats are in use");
+        let (token, status) = seat_license(Some(2));
+        let cache = ActivationCache::default();
+        cache.insert(
+            &token,
+            serde_json::from_str::<ActivationsResponse>(&activations_body(&["other"]))
+                .unwrap()
+                .activations,
+        );
+        let bound = bind_seat(status, &token, "this-device", &base_url, &cache).await;
+        assert!(!bound.valid);
+        assert_eq!(bound.error, Some(LicenseError::SeatsExhausted(2)));
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::{device, licensing, storage};
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+/// The pending "license expires soon" notification, if one is scheduled
+#[derive(Default)]
+pub struct ExpiryReminder {
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Replace any pending reminder with one for the stored license, and return
+/// when it will fire. Nothing is scheduled for a missing, invalid, perpetual
+/// or already lapsed license. A reminder whose time has already passed fires right away.
+pub fn reschedule(app: &AppHandle) -> Option<DateTime<Utc>> {
+    let reminder = app.state::<ExpiryReminder>();
+    let mut task = reminder.task.lock().ok()?;
+    if let Some(previous) = task.take() {
+        previous.abort();
+    }
+
+    let token = storage::read_token(app).ok().flatten()?;
+    let status = licensing::verify_license_token(&token, &device::device_fingerprint());
+    if !status.valid || status.grace_period {
+        return None;
+    }
+    let expires_at = DateTime::parse_from_rfc3339(status.expires_at.as_deref()?).ok()?;
+    let remind_at = licensing::expiry_reminder_at(status.expires_at.as_deref())?;
+
+    let delay = (remind_at - Utc::now()).to_std().unwrap_or_default();
+    let body = format!(
+        "Your LoCalendar license expires on {}.",
+        licensing::format_local_date(&expires_at)
+    );
+    let app = app.clone();
+    *task = Some(tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let _ = app
+            .notification()
+            .builder()
+            .title("License expiring soon")
+            .body(body)
+            .show();
+    }));
+
+    Some(remind_at)
+}
@@ -0,0 +1,808 @@
+use crate::licensing::{
+    advance_clock_anchor, is_valid_public_key, verify_activation_response, verify_license_token,
+    LicenseStatus, PinnedKey,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+const TOKEN_FILE: &str = "license.token";
+const META_FILE: &str = "license_meta.json";
+const PINNED_KEY_FILE: &str = "pinned_public_key";
+const ACTIVATION_FILE: &str = "offline_activation";
+
+/// Bookkeeping kept alongside the stored token
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct LicenseMeta {
+    /// When the server last confirmed the stored token
+    pub last_verified_at: Option<String>,
+    /// Result of that confirmation
+    pub cached_status: Option<LicenseStatus>,
+    /// Latest trusted time seen (from the server's signed clock). The local
+    /// clock being behind this means it was rolled back.
+    #[serde(default)]
+    pub clock_high_water: Option<String>,
+}
+
+impl LicenseMeta {
+    /// Raise the clock high-water mark to a server time we trust
+    pub fn observe_server_time(&mut self, server_time: DateTime<Utc>) {
+        self.clock_high_water = Some(advance_clock_anchor(
+            self.clock_high_water.as_deref(),
+            server_time,
+        ));
+    }
+
+    /// Record a fresh answer from the server: when it came, what it said and
+    /// its signed clock, if it sent one
+    pub fn record_verification(
+        &mut self,
+        status: &LicenseStatus,
+        server_time: Option<DateTime<Utc>>,
+    ) {
+        self.last_verified_at = Some(Utc::now().to_rfc3339());
+        self.cached_status = Some(status.clone());
+        if let Some(server_time) = server_time {
+            self.observe_server_time(server_time);
+        }
+    }
+}
+
+fn data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data dir: {}", e))
+}
+
+/// Read the stored license token, if one has been saved
+pub fn read_token(app: &AppHandle) -> Result<Option<String>, String> {
+    read_token_in(&data_dir(app)?)
+}
+
+fn read_token_in(dir: &Path) -> Result<Option<String>, String> {
+    let path = dir.join(TOKEN_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(|token| Some(token.trim().to_string()))
+        .map_err(|e| format!("Failed to read license: {}", e))
+}
+
+/// Read the stored metadata. Missing or unreadable metadata is treated as empty.
+pub fn read_meta(app: &AppHandle) -> LicenseMeta {
+    data_dir(app)
+        .map(|dir| read_meta_in(&dir))
+        .unwrap_or_default()
+}
+
+fn read_meta_in(dir: &Path) -> LicenseMeta {
+    fs::read_to_string(dir.join(META_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Write via a temp file and rename so readers never see a half-written file
+fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create app data dir: {}", e))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+pub fn write_meta(app: &AppHandle, meta: &LicenseMeta) -> Result<(), String> {
+    write_meta_in(&data_dir(app)?, meta)
+}
+
+fn write_meta_in(dir: &Path, meta: &LicenseMeta) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(meta)
+        .map_err(|e| format!("Failed to serialize license metadata: {}", e))?;
+    write_atomic(&dir.join(META_FILE), &json)
+}
+
+/// Replace the stored token atomically
+pub fn write_token(app: &AppHandle, token: &str) -> Result<(), String> {
+    write_atomic(&data_dir(app)?.join(TOKEN_FILE), token)
+}
+
+/// Store `token` in place of the current license and drop what was cached
+/// about the old one
+pub fn replace_token(app: &AppHandle, token: &str) -> Result<(), String> {
+    replace_token_in(&data_dir(app)?, token)
+}
+
+fn replace_token_in(dir: &Path, token: &str) -> Result<(), String> {
+    write_atomic(&dir.join(TOKEN_FILE), token)?;
+    // Cached server results belonged to the previous token; the clock
+    // high-water mark doesn't
+    let meta = LicenseMeta {
+        clock_high_water: read_meta_in(dir).clock_high_water,
+        ..Default::default()
+    };
+    write_meta_in(dir, &meta)
+}
+
+/// Verify `token` and, only if it is valid, store it in place of the current
+/// license. The status is returned either way; an invalid token leaves the
+/// stored files untouched.
+pub fn save_if_valid(
+    app: &AppHandle,
+    token: &str,
+    fingerprint: &str,
+) -> Result<LicenseStatus, String> {
+    save_if_valid_in(&data_dir(app)?, token, fingerprint)
+}
+
+fn save_if_valid_in(dir: &Path, token: &str, fingerprint: &str) -> Result<LicenseStatus, String> {
+    let status = verify_license_token(token, fingerprint);
+    if status.valid {
+        replace_token_in(dir, token)?;
+    }
+    Ok(status)
+}
+
+/// The server public key pinned on first use, if any
+pub fn read_pinned_key(app: &AppHandle) -> Result<Option<PinnedKey>, String> {
+    read_pinned_key_in(&data_dir(app)?)
+}
+
+fn read_pinned_key_in(dir: &Path) -> Result<Option<PinnedKey>, String> {
+    let path = dir.join(PINNED_KEY_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read pinned key: {}", e))?;
+    Ok(Some(parse_pinned_key(&contents)))
+}
+
+/// Older builds stored the bare base64 key, without an endorsement
+fn parse_pinned_key(contents: &str) -> PinnedKey {
+    serde_json::from_str(contents).unwrap_or_else(|_| PinnedKey {
+        public_key: contents.trim().to_string(),
+        endorsement: None,
+    })
+}
+
+pub fn write_pinned_key(app: &AppHandle, key: &PinnedKey) -> Result<(), String> {
+    write_pinned_key_in(&data_dir(app)?, key)
+}
+
+fn write_pinned_key_in(dir: &Path, key: &PinnedKey) -> Result<(), String> {
+    let json =
+        serde_json::to_string(key).map_err(|e| format!("Failed to serialize pinned key: {}", e))?;
+    write_atomic(&dir.join(PINNED_KEY_FILE), &json)
+}
+
+/// The imported offline activation response, if any
+pub fn read_activation(app: &AppHandle) -> Result<Option<String>, String> {
+    let path = data_dir(app)?.join(ACTIVATION_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(|response| Some(response.trim().to_string()))
+        .map_err(|e| format!("Failed to read offline activation: {}", e))
+}
+
+pub fn write_activation(app: &AppHandle, response: &str) -> Result<(), String> {
+    write_atomic(&data_dir(app)?.join(ACTIVATION_FILE), response)
+}
+
+/// Delete every licensing file: the token, its metadata (including the clock
+/// high-water mark), the pinned server key and any offline activation.
+/// Missing files are fine.
+pub fn remove_all(app: &AppHandle) -> Result<(), String> {
+    remove_all_in(&data_dir(app)?)
+}
+
+fn remove_all_in(dir: &Path) -> Result<(), String> {
+    for file in [TOKEN_FILE, META_FILE, PINNED_KEY_FILE, ACTIVATION_FILE] {
+        let path = dir.join(file);
+        if path.exists() {
+            fs::remove_file(&path)
+                .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+// Bumped whenever the backup layout changes incompatibly
+const BACKUP_VERSION: u32 = 1;
+
+/// The licensing state worth carrying to another machine. Offline
+/// activations and cached server verdicts are tied to this machine or token
+/// and are rebuilt there instead.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LicensingBackup {
+    pub token: String,
+    pub clock_high_water: Option<String>,
+    pub pinned_public_key: Option<String>,
+    /// The pinned key's endorsement (see `PinnedKey`). Absent from backups
+    /// made before pins carried one, which keeps their checksums valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_key_endorsement: Option<String>,
+    pub exported_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupFile {
+    version: u32,
+    backup: LicensingBackup,
+    /// Hex SHA-256 of `backup` serialized as compact JSON
+    checksum: String,
+}
+
+fn backup_checksum(backup: &LicensingBackup) -> Result<String, String> {
+    let json =
+        serde_json::to_string(backup).map_err(|e| format!("Failed to serialize backup: {}", e))?;
+    Ok(Sha256::digest(json.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Bundle the stored license and its metadata into a JSON backup
+pub fn export_backup(app: &AppHandle) -> Result<String, String> {
+    export_backup_in(&data_dir(app)?)
+}
+
+fn export_backup_in(dir: &Path) -> Result<String, String> {
+    let pin = read_pinned_key_in(dir)?;
+    let backup = LicensingBackup {
+        token: read_token_in(dir)?.ok_or("No license stored")?,
+        clock_high_water: read_meta_in(dir).clock_high_water,
+        pinned_key_endorsement: pin.as_ref().and_then(|pin| pin.endorsement.clone()),
+        pinned_public_key: pin.map(|pin| pin.public_key),
+        exported_at: Utc::now().to_rfc3339(),
+    };
+    let file = BackupFile {
+        version: BACKUP_VERSION,
+        checksum: backup_checksum(&backup)?,
+        backup,
+    };
+    serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize backup: {}", e))
+}
+
+/// Parse a backup made by `export_backup`, rejecting it if it was corrupted
+pub fn parse_backup(blob: &str) -> Result<LicensingBackup, String> {
+    let file: BackupFile =
+        serde_json::from_str(blob.trim()).map_err(|_| "Not a licensing backup".to_string())?;
+    if file.version != BACKUP_VERSION {
+        return Err(format!("Unsupported backup version {}", file.version));
+    }
+    if backup_checksum(&file.backup)? != file.checksum {
+        return Err("Backup is corrupted (checksum mismatch)".to_string());
+    }
+    Ok(file.backup)
+}
+
+/// Restore a backup in place of the stored license. The token must verify
+/// first, and a backed-up pinned key must be one this build trusts (the
+/// checksum only catches corruption, not edits); otherwise nothing changes.
+/// The clock high-water mark only ever moves forward, and the pinned key is
+/// only used if none is pinned here.
+pub fn import_backup(
+    app: &AppHandle,
+    blob: &str,
+    fingerprint: &str,
+) -> Result<LicenseStatus, String> {
+    import_backup_in(&data_dir(app)?, blob, fingerprint)
+}
+
+fn import_backup_in(dir: &Path, blob: &str, fingerprint: &str) -> Result<LicenseStatus, String> {
+    let backup = parse_backup(blob)?;
+    let status = verify_license_token(&backup.token, fingerprint);
+    if !status.valid {
+        return Err(format!(
+            "Backup license is not valid: {}",
+            status
+                .error
+                .map_or_else(|| "unknown error".to_string(), |e| e.to_string())
+        ));
+    }
+    let backup_key = backup.pinned_public_key.map(|public_key| PinnedKey {
+        public_key,
+        endorsement: backup.pinned_key_endorsement,
+    });
+    if backup_key.as_ref().is_some_and(|key| !key.is_trusted()) {
+        return Err(
+            "Backup pins a server key this build doesn't trust; not restoring it".to_string(),
+        );
+    }
+    if read_pinned_key_in(dir)?.is_none() {
+        if let Some(key) = backup_key {
+            write_pinned_key_in(dir, &key)?;
+        }
+    }
+
+    let mut meta = LicenseMeta {
+        clock_high_water: read_meta_in(dir).clock_high_water,
+        ..Default::default()
+    };
+    if let Some(time) = backup
+        .clock_high_water
+        .as_deref()
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+    {
+        meta.observe_server_time(time.with_timezone(&Utc));
+    }
+    write_atomic(&dir.join(TOKEN_FILE), &backup.token)?;
+    write_meta_in(dir, &meta)?;
+    Ok(status)
+}
+
+/// One problem found by `check_integrity`
+#[derive(Debug, Serialize, Clone)]
+pub struct IntegrityProblem {
+    pub file: String,
+    pub problem: String,
+}
+
+/// What support gets back from an integrity check of the licensing files
+#[derive(Debug, Default, Serialize, Clone)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    pub problems: Vec<IntegrityProblem>,
+}
+
+impl IntegrityReport {
+    fn problem(&mut self, file: &str, problem: impl Into<String>) {
+        self.problems.push(IntegrityProblem {
+            file: file.to_string(),
+            problem: problem.into(),
+        });
+    }
+}
+
+fn read_if_present(path: &Path) -> Result<Option<String>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(path)
+        .map(|contents| Some(contents.trim().to_string()))
+        .map_err(|e| e.to_string())
+}
+
+fn is_rfc3339(time: &str) -> bool {
+    DateTime::parse_from_rfc3339(time).is_ok()
+}
+
+/// Check every licensing file in the app data dir: that each one parses, the
+/// token verifies, and the metadata, pinned key and offline activation agree
+/// with it. Only reads; nothing is repaired.
+pub fn check_integrity(app: &AppHandle, machine_id: &str) -> Result<IntegrityReport, String> {
+    Ok(check_dir(&data_dir(app)?, machine_id))
+}
+
+fn check_dir(dir: &Path, machine_id: &str) -> IntegrityReport {
+    let mut report = IntegrityReport::default();
+    let read = |file: &str, report: &mut IntegrityReport| match read_if_present(&dir.join(file)) {
+        Ok(contents) => contents,
+        Err(e) => {
+            report.problem(file, format!("Unreadable: {}", e));
+            None
+        }
+    };
+
+    let token = read(TOKEN_FILE, &mut report);
+    let payload = token.as_deref().and_then(|token| {
+        let status = verify_license_token(token, machine_id);
+        if status.payload.is_none() {
+            report.problem(
+                TOKEN_FILE,
+                status
+                    .error
+                    .map_or_else(|| "Token does not verify".to_string(), |e| e.to_string()),
+            );
+        }
+        status.payload
+    });
+
+    if let Some(contents) = read(META_FILE, &mut report) {
+        match serde_json::from_str::<LicenseMeta>(&contents) {
+            Err(e) => report.problem(META_FILE, format!("Corrupt JSON: {}", e)),
+            Ok(meta) => {
+                if token.is_none() {
+                    report.problem(META_FILE, "Metadata present without a license token");
+                }
+                if meta
+                    .last_verified_at
+                    .as_deref()
+                    .is_some_and(|t| !is_rfc3339(t))
+                {
+                    report.problem(META_FILE, "last_verified_at is not a valid timestamp");
+                }
+                if meta
+                    .clock_high_water
+                    .as_deref()
+                    .is_some_and(|t| !is_rfc3339(t))
+                {
+                    report.problem(META_FILE, "clock_high_water is not a valid timestamp");
+                }
+                let cached = meta.cached_status.and_then(|status| status.payload);
+                if let (Some(cached), Some(payload)) = (cached, payload.as_ref()) {
+                    if cached.email != payload.email || cached.issued_at != payload.issued_at {
+                        report.problem(META_FILE, "Cached status is for a different license");
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(contents) = read(PINNED_KEY_FILE, &mut report) {
+        let pin = parse_pinned_key(&contents);
+        if !is_valid_public_key(&pin.public_key) {
+            report.problem(PINNED_KEY_FILE, "Not a valid public key");
+        } else if !pin.is_trusted() {
+            report.problem(
+                PINNED_KEY_FILE,
+                "Not endorsed by a key this build trusts; ignored",
+            );
+        }
+    }
+
+    if let Some(response) = read(ACTIVATION_FILE, &mut report) {
+        match token.as_deref() {
+            None => report.problem(
+                ACTIVATION_FILE,
+                "Offline activation present without a license token",
+            ),
+            Some(token) => {
+                if let Err(e) = verify_activation_response(&response, token, machine_id) {
+                    report.problem(ACTIVATION_FILE, e);
+                }
+            }
+        }
+    }
+
+    // Left behind when a write was interrupted before its rename
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                report.problem(
+                    &entry.file_name().to_string_lossy(),
+                    "Leftover temp file from an interrupted write",
+                );
+            }
+        }
+    }
+
+    report.ok = report.problems.is_empty();
+    report
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use crate::licensing::generate_demo_license;
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn recording_a_verification_replaces_the_cached_answer() {
+        let token = generate_demo_license("user@example.com");
+        let status = verify_license_token(&token, "this-device");
+        let mut meta = LicenseMeta {
+            last_verified_at: Some("2020-01-01T00:00:00+00:00".to_string()),
+            cached_status: None,
+            clock_high_water: Some("2025-01-01T00:00:00+00:00".to_string()),
+        };
+
+        let before = Utc::now();
+        meta.record_verification(&status, Some(time("2025-06-01T00:00:00Z")));
+        let verified_at = time(meta.last_verified_at.as_deref().unwrap());
+        assert!(verified_at >= before - chrono::Duration::seconds(1));
+        let cached = meta.cached_status.as_ref().unwrap();
+        assert!(cached.valid);
+        assert_eq!(cached.payload, status.payload);
+        assert_eq!(
+            meta.clock_high_water.as_deref(),
+            Some("2025-06-01T00:00:00+00:00")
+        );
+
+        // No signed clock leaves the high-water mark alone
+        meta.record_verification(&status, None);
+        assert_eq!(
+            meta.clock_high_water.as_deref(),
+            Some("2025-06-01T00:00:00+00:00")
+        );
+    }
+
+    /// A fresh, empty data dir for one test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "localendar-storage-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn valid_token_is_saved_and_cached_results_dropped() {
+        let dir = scratch_dir("save-valid");
+        write_meta_in(
+            &dir,
+            &LicenseMeta {
+                last_verified_at: Some("2025-01-01T00:00:00+00:00".to_string()),
+                cached_status: None,
+                clock_high_water: Some("2025-02-01T00:00:00+00:00".to_string()),
+            },
+        )
+        .unwrap();
+
+        let token = generate_demo_license("user@example.com");
+        let status = save_if_valid_in(&dir, &token, "this-device").unwrap();
+        assert!(status.valid);
+        assert_eq!(fs::read_to_string(dir.join(TOKEN_FILE)).unwrap(), token);
+        let meta = read_meta_in(&dir);
+        assert_eq!(meta.last_verified_at, None);
+        assert_eq!(
+            meta.clock_high_water.as_deref(),
+            Some("2025-02-01T00:00:00+00:00")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_token_leaves_the_stored_files_alone() {
+        let dir = scratch_dir("save-invalid");
+        let stored = generate_demo_license("user@example.com");
+        save_if_valid_in(&dir, &stored, "this-device").unwrap();
+        let meta_before = fs::read_to_string(dir.join(META_FILE)).unwrap();
+
+        let status = save_if_valid_in(&dir, "not-a-token", "this-device").unwrap();
+        assert!(!status.valid);
+        assert!(status.error.is_some());
+        assert_eq!(fs::read_to_string(dir.join(TOKEN_FILE)).unwrap(), stored);
+        assert_eq!(
+            fs::read_to_string(dir.join(META_FILE)).unwrap(),
+            meta_before
+        );
+
+        // Nor does it create anything when nothing was stored
+        let empty = scratch_dir("save-invalid-empty");
+        assert!(
+            !save_if_valid_in(&empty, "not-a-token", "this-device")
+                .unwrap()
+                .valid
+        );
+        assert!(!empty.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_all_leaves_no_licensing_files() {
+        let dir = scratch_dir("remove-all");
+        save_if_valid_in(
+            &dir,
+            &generate_demo_license("user@example.com"),
+            "this-device",
+        )
+        .unwrap();
+        let mut meta = read_meta_in(&dir);
+        meta.observe_server_time(time("2025-06-01T00:00:00Z"));
+        write_meta_in(&dir, &meta).unwrap();
+        for file in [PINNED_KEY_FILE, ACTIVATION_FILE, "unrelated.txt"] {
+            write_atomic(&dir.join(file), "contents").unwrap();
+        }
+
+        remove_all_in(&dir).unwrap();
+        for file in [TOKEN_FILE, META_FILE, PINNED_KEY_FILE, ACTIVATION_FILE] {
+            assert!(!dir.join(file).exists(), "{} survived", file);
+        }
+        assert!(read_meta_in(&dir).clock_high_water.is_none());
+        // Only licensing files go
+        assert!(dir.join("unrelated.txt").exists());
+
+        // Nothing left to remove is fine
+        remove_all_in(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        remove_all_in(&dir).unwrap();
+    }
+
+    /// The (file, problem) pairs a check of `dir` reports
+    fn problems(dir: &Path) -> Vec<(String, String)> {
+        let report = check_dir(dir, "this-device");
+        assert_eq!(report.ok, report.problems.is_empty());
+        report
+            .problems
+            .into_iter()
+            .map(|p| (p.file, p.problem))
+            .collect()
+    }
+
+    #[test]
+    fn consistent_store_passes_the_integrity_check() {
+        let dir = scratch_dir("integrity-ok");
+        assert!(problems(&dir).is_empty());
+
+        save_if_valid_in(
+            &dir,
+            &generate_demo_license("user@example.com"),
+            "this-device",
+        )
+        .unwrap();
+        assert!(problems(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_metadata_is_reported() {
+        let dir = scratch_dir("integrity-corrupt");
+        save_if_valid_in(
+            &dir,
+            &generate_demo_license("user@example.com"),
+            "this-device",
+        )
+        .unwrap();
+        fs::write(dir.join(META_FILE), "{ not json").unwrap();
+
+        let found = problems(&dir);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(found[0].0, META_FILE);
+        assert!(found[0].1.starts_with("Corrupt JSON"), "{}", found[0].1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inconsistent_files_are_reported() {
+        let dir = scratch_dir("integrity-inconsistent");
+        write_meta_in(
+            &dir,
+            &LicenseMeta {
+                last_verified_at: Some("yesterday".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        write_atomic(&dir.join(PINNED_KEY_FILE), "not-a-key").unwrap();
+        fs::write(dir.join("license.tmp"), "half").unwrap();
+
+        let found = problems(&dir);
+        for (file, problem) in [
+            (META_FILE, "Metadata present without a license token"),
+            (META_FILE, "last_verified_at is not a valid timestamp"),
+            (PINNED_KEY_FILE, "Not a valid public key"),
+            (
+                "license.tmp",
+                "Leftover temp file from an interrupted write",
+            ),
+        ] {
+            assert!(
+                found.contains(&(file.to_string(), problem.to_string())),
+                "{} / {} missing from {:?}",
+                file,
+                problem,
+                found
+            );
+        }
+
+        fs::write(dir.join(TOKEN_FILE), "garbage").unwrap();
+        assert!(problems(&dir).iter().any(|(file, _)| file == TOKEN_FILE));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    const PINNED_KEY: &str = "80mvE4OaCnNhvgBh63xbFCHLa0tZCV8PMdlXRT8AgpY=";
+
+    #[test]
+    fn pinned_keys_round_trip_and_unendorsed_ones_are_reported() {
+        let dir = scratch_dir("pinned-key");
+        // Older builds wrote the bare key
+        write_atomic(&dir.join(PINNED_KEY_FILE), &format!("{}\n", PINNED_KEY)).unwrap();
+        let legacy = read_pinned_key_in(&dir).unwrap().unwrap();
+        assert_eq!(legacy.public_key, PINNED_KEY);
+        assert_eq!(legacy.endorsement, None);
+        assert!(problems(&dir).is_empty());
+
+        let unendorsed = PinnedKey {
+            public_key: "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=".to_string(),
+            endorsement: Some("AAAA".to_string()),
+        };
+        write_pinned_key_in(&dir, &unendorsed).unwrap();
+        assert_eq!(read_pinned_key_in(&dir).unwrap(), Some(unendorsed));
+        assert_eq!(
+            problems(&dir),
+            vec![(
+                PINNED_KEY_FILE.to_string(),
+                "Not endorsed by a key this build trusts; ignored".to_string()
+            )]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_round_trips_to_another_machine() {
+        let from = scratch_dir("backup-from");
+        let token = generate_demo_license("user@example.com");
+        replace_token_in(&from, &token).unwrap();
+        write_meta_in(
+            &from,
+            &LicenseMeta {
+                clock_high_water: Some("2025-06-01T00:00:00+00:00".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        write_atomic(&from.join(PINNED_KEY_FILE), PINNED_KEY).unwrap();
+
+        let blob = export_backup_in(&from).unwrap();
+        let to = scratch_dir("backup-to");
+        let status = import_backup_in(&to, &blob, "other-device").unwrap();
+        assert!(status.valid);
+        assert_eq!(read_token_in(&to).unwrap(), Some(token));
+        assert_eq!(
+            read_pinned_key_in(&to).unwrap().map(|pin| pin.public_key),
+            Some(PINNED_KEY.to_string())
+        );
+        assert_eq!(
+            read_meta_in(&to).clock_high_water.as_deref(),
+            Some("2025-06-01T00:00:00+00:00")
+        );
+
+        fs::remove_dir_all(&from).unwrap();
+        fs::remove_dir_all(&to).unwrap();
+        assert!(export_backup_in(&from).is_err());
+    }
+
+    #[test]
+    fn corrupted_or_invalid_backups_change_nothing() {
+        let from = scratch_dir("backup-corrupt");
+        replace_token_in(&from, &generate_demo_license("user@example.com")).unwrap();
+        let blob = export_backup_in(&from).unwrap();
+        let to = scratch_dir("backup-untouched");
+
+        let edited = blob.replace(
+            "\"clock_high_water\": null",
+            "\"clock_high_water\": \"2099-01-01T00:00:00Z\"",
+        );
+        assert_ne!(edited, blob);
+        let error = import_backup_in(&to, &edited, "this-device").unwrap_err();
+        assert_eq!(error, "Backup is corrupted (checksum mismatch)");
+        assert!(import_backup_in(&to, "{ not json", "this-device").is_err());
+
+        // A matching checksum over a token that doesn't verify
+        let mut file: BackupFile = serde_json::from_str(&blob).unwrap();
+        file.backup.token = "not-a-token".to_string();
+        file.checksum = backup_checksum(&file.backup).unwrap();
+        let forged = serde_json::to_string(&file).unwrap();
+        let error = import_backup_in(&to, &forged, "this-device").unwrap_err();
+        assert!(
+            error.starts_with("Backup license is not valid"),
+            "{}",
+            error
+        );
+
+        // A matching checksum over an attacker's key, with or without an
+        // endorsement it made for itself
+        let mut file: BackupFile = serde_json::from_str(&blob).unwrap();
+        file.backup.pinned_public_key =
+            Some("11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=".to_string());
+        for endorsement in [None, Some("AAAA".to_string())] {
+            file.backup.pinned_key_endorsement = endorsement;
+            file.checksum = backup_checksum(&file.backup).unwrap();
+            let forged = serde_json::to_string(&file).unwrap();
+            let error = import_backup_in(&to, &forged, "this-device").unwrap_err();
+            assert!(error.contains("doesn't trust"), "{}", error);
+        }
+
+        assert!(!to.exists());
+        fs::remove_dir_all(&from).unwrap();
+    }
+}
@@ -7,12 +7,15 @@ export interface LicensePayload {
   plan: string;
   issued_at: string;
   expires_at?: string;
+  seats?: number;
 }
 
 export interface LicenseStatus {
   valid: boolean;
   payload?: LicensePayload;
   expires_at?: string;
+  days_remaining?: number;
+  seats?: number;
   grace_period: boolean;
   error?: string;
 }